@@ -1,27 +1,240 @@
-extern crate rfs;
-use rfs::config::RfsConfig;
-use rfs::rfs_client::{Client, RfsClientSession};
+#![allow(clippy::redundant_field_names)]
+
 #[macro_use]
 extern crate log;
-extern crate env_logger;
+use rfs::cli::{fail, start_logger, DEFAULT_CLIENT_NAME, DEFAULT_CONFIG_PATH,
+               DEFAULT_SERVER_NAME, USAGE_ERROR};
+use rfs::config::RfsConfig;
+use rfs::message::{FileKind, FileStat, Timestamp};
+use rfs::rfs_client::{Client, RfsClientError, RfsClientSession};
+use getopts::{Options, ParsingStyle};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
+
+const COMMANDS: &str = "Commands:
+    put <local file> <remote file>
+    get <remote file> <local file>
+    ls [<remote directory>]
+    rm <remote file>
+    mkdir <remote directory>
+    rmdir <remote directory>
+    stat <remote path>
+    mv <remote path> <new remote path>";
 
 fn main() {
-    start_logger();
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    opts.optopt("c", "config", "configuration file", "FILE");
+    opts.optopt("s", "server", "name of the server in the configuration", "NAME");
+    opts.optopt("n", "client", "name of this client in the configuration", "NAME");
+    opts.optopt("l", "log-level", "log level, or RUST_LOG-like filters", "LEVEL");
+    opts.optopt("", "chunk-size", "number of bytes sent or received per request", "BYTES");
+    opts.optflag("q", "quiet", "do not show the progress of transfers");
+    opts.optflag("h", "help", "print this help");
+    let usage = format!("Usage: {} [options] <command> [arguments]", args[0]);
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}\n{}\n{}", e, opts.usage(&usage), COMMANDS);
+            process::exit(USAGE_ERROR);
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}\n{}", opts.usage(&usage), COMMANDS);
+        return;
+    }
+    let command: Vec<&str> = matches.free.iter().map(String::as_str).collect();
+    if !is_valid(&command) {
+        eprintln!("{}\n{}", opts.usage(&usage), COMMANDS);
+        process::exit(USAGE_ERROR);
+    }
+    let chunk_size = match matches.opt_str("chunk-size").map(|s| s.parse::<usize>()) {
+        Some(Ok(0)) | Some(Err(_)) => {
+            eprintln!("Invalid chunk size\n{}\n{}", opts.usage(&usage), COMMANDS);
+            process::exit(USAGE_ERROR);
+        }
+        Some(Ok(size)) => Some(size),
+        None => None,
+    };
+    start_logger(matches.opt_str("l"));
 
-    let config = RfsConfig::from("assets/rfs_config");
+    let config_path = matches.opt_str("c").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+    let config = match RfsConfig::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => fail(&format!("Invalid configuration. Reason: {}", e)),
+    };
 
-    match RfsClientSession::new(String::from("srv1"), String::from("cli1"), config) {
-        Some(mut c) => {
-            c.connect();
-            c.disconnect().expect("Disconnection failed");
-        }
-        None => (),
+    let server = matches.opt_str("s").unwrap_or(DEFAULT_SERVER_NAME.to_string());
+    let client = matches.opt_str("n").unwrap_or(DEFAULT_CLIENT_NAME.to_string());
+    let mut session = match RfsClientSession::new(server, client, config) {
+        Some(session) => session,
+        None => fail("Could not reach the server"),
     };
+    if let Err(e) = session.connect() {
+        fail(&format!("Could not connect. Reason: {}", e));
+    }
+    if let Some(size) = chunk_size {
+        session.set_chunk_length(size);
+    }
+    let progress = !matches.opt_present("q") && io::stderr().is_terminal();
+    let result = run(&mut session, &command, progress);
+    if let Err(e) = session.disconnect() {
+        warn!("Could not shutdown connection: {}", e);
+    }
+    if let Err(e) = result {
+        fail(&format!("{}: {}", command[0], e));
+    }
+}
+
+/// Returns whether `command` is a known command, with the right number of arguments.
+fn is_valid(command: &[&str]) -> bool {
+    matches!(
+        *command,
+        ["put", _, _] | ["get", _, _] | ["mv", _, _] | ["ls"] | ["ls", _] |
+        ["rm", _] | ["mkdir", _] | ["rmdir", _] | ["stat", _]
+    )
+}
+
+fn run(session: &mut RfsClientSession, command: &[&str], progress: bool) -> Result<(), String> {
+    let remote = |e: RfsClientError| e.to_string();
+    match *command {
+        ["put", local, path] => put(session, local, path, progress),
+        ["get", path, local] => get(session, path, local, progress),
+        ["ls"] => ls(session, ""),
+        ["ls", path] => ls(session, path),
+        ["rm", path] => session.unlink(path).map_err(remote),
+        ["mkdir", path] => session.mkdir(path).map_err(remote),
+        ["rmdir", path] => session.rmdir(path).map_err(remote),
+        ["stat", path] => {
+            let stat = session.stat(path).map_err(remote)?;
+            print_stat(path, &stat);
+            Ok(())
+        }
+        ["mv", from, to] => session.rename(from, to).map_err(remote),
+        _ => Err(String::from("Unknown command")),
+    }
+}
+
+/// Upload the local file `local` into the remote file `path`, replacing it if it exists.
+fn put(
+    session: &mut RfsClientSession,
+    local: &str,
+    path: &str,
+    progress: bool,
+) -> Result<(), String> {
+    let file = File::open(local).map_err(|e| format!("{}: {}", local, e))?;
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    session
+        .upload(Progress::new(file, progress, size), path)
+        .map_err(|e| e.to_string())?;
+    end_progress(progress);
+    Ok(())
+}
+
+/// Download the remote file `path` into the local file `local`. The content is written to a
+/// temporary file first, renamed to `local` once complete, so that a failed download leaves
+/// `local` as it was.
+fn get(
+    session: &mut RfsClientSession,
+    path: &str,
+    local: &str,
+    progress: bool,
+) -> Result<(), String> {
+    let size = session.stat(path).map_err(|e| e.to_string())?.get_size();
+    let temporary = format!("{}.part", local);
+    let file = File::create(&temporary).map_err(|e| format!("{}: {}", temporary, e))?;
+    let result = session
+        .download(path, Progress::new(file, progress, size))
+        .map_err(|e| e.to_string())
+        .and_then(|_| fs::rename(&temporary, local).map_err(|e| format!("{}: {}", local, e)));
+    end_progress(progress);
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(&temporary) {
+            warn!("Could not remove {}. Reason: {}", temporary, e);
+        }
+    }
+    result
+}
+
+fn ls(session: &mut RfsClientSession, path: &str) -> Result<(), String> {
+    let mut entries = session.list_dir(path).map_err(|e| e.to_string())?;
+    entries.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    for entry in entries {
+        let suffix = match entry.get_kind() {
+            FileKind::Directory => "/",
+            FileKind::Symlink => "@",
+            _ => "",
+        };
+        println!("{}{}", String::from_utf8_lossy(entry.get_name()), suffix);
+    }
+    Ok(())
+}
+
+fn print_stat(path: &str, stat: &FileStat) {
+    println!("  Path: {}", path);
+    println!("  Kind: {:?}", stat.get_kind());
+    println!("  Size: {}", stat.get_size());
+    println!("  Mode: {:o}", stat.get_mode());
+    println!("Access: {}", format_timestamp(stat.get_accessed()));
+    println!("Modify: {}", format_timestamp(stat.get_modified()));
+}
+
+fn format_timestamp(timestamp: Timestamp) -> String {
+    format!("{}.{:09}", timestamp.get_seconds(), timestamp.get_nanoseconds())
+}
+
+/// A local file being transferred, which shows the progress of the transfer if `progress` is set.
+struct Progress<F> {
+    file: F,
+    progress: bool,
+    done: u64,
+    total: u64,
+}
+
+impl<F> Progress<F> {
+    fn new(file: F, progress: bool, total: u64) -> Self {
+        Progress {
+            file: file,
+            progress: progress,
+            done: 0,
+            total: total,
+        }
+    }
+
+    fn advance(&mut self, length: usize) {
+        self.done += length as u64;
+        if self.progress {
+            let percent = (self.done * 100).checked_div(self.total).unwrap_or(100);
+            eprint!("\r{} / {} bytes ({}%)", self.done, self.total, percent);
+        }
+    }
+}
+
+impl<F: Read> Read for Progress<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        self.advance(read);
+        Ok(read)
+    }
+}
+
+impl<F: Write> Write for Progress<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.advance(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
 }
 
-fn start_logger() {
-    match env_logger::init() {
-        Ok(()) => info!("Logger started"),
-        Err(e) => print!("Error during logger initialisation. Reason: {}", e),
+fn end_progress(progress: bool) {
+    if progress {
+        eprintln!();
     }
 }
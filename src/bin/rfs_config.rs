@@ -0,0 +1,117 @@
+use rfs::cli::{fail, FAILURE, USAGE_ERROR};
+use rfs::config::{Field, Permissions, RfsConfig};
+use rfs::config_file::ConfigFile;
+use rfs::rfs_common::{BlowfishKey, generate_key, MIN_KEY_LENGTH, MAX_KEY_LENGTH};
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage:
+    rfs-config check <config file>...
+    rfs-config keygen [<length>]
+    rfs-config add-client <config file> <name> [<root>]
+    rfs-config add-server <config file> <name> <address> <port> [<root>]
+    rfs-config remove <config file> <name>
+    rfs-config rotate <config file> <name>";
+
+/// Length of the generated keys, in bytes.
+const DEFAULT_KEY_LENGTH: usize = 32;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match *args.as_slice() {
+        ["check", ref files @ ..] if !files.is_empty() => check(files),
+        ["keygen"] => println!("{}", base64::encode(&key(DEFAULT_KEY_LENGTH))),
+        ["keygen", length] => {
+            match length.parse() {
+                Ok(length) if (MIN_KEY_LENGTH..=MAX_KEY_LENGTH).contains(&length) => {
+                    println!("{}", base64::encode(&key(length)))
+                }
+                _ => {
+                    fail(&format!(
+                        "The length of a key must be between {} and {} bytes",
+                        MIN_KEY_LENGTH,
+                        MAX_KEY_LENGTH
+                    ))
+                }
+            }
+        }
+        ["add-client", file, name, ref root @ ..] if root.len() <= 1 => {
+            let field = Field::Client {
+                name: name.to_string(),
+                key: key(DEFAULT_KEY_LENGTH),
+                root: root.first().map(|r| r.to_string()),
+                permissions: Permissions::default(),
+            };
+            edit(file, |config_file| config_file.add(&field))
+        }
+        ["add-server", file, name, address, port, ref root @ ..] if root.len() <= 1 => {
+            let field = Field::Server {
+                name: name.to_string(),
+                key: key(DEFAULT_KEY_LENGTH),
+                address: address.to_string(),
+                port: port.to_string(),
+                tls: None,
+                root: root.first().map(|r| r.to_string()),
+            };
+            edit(file, |config_file| config_file.add(&field))
+        }
+        ["remove", file, name] => edit(file, |config_file| config_file.remove(name)),
+        ["rotate", file, name] => {
+            let key = key(DEFAULT_KEY_LENGTH);
+            edit(file, |config_file| config_file.rotate(name, &key))
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(USAGE_ERROR);
+        }
+    }
+}
+
+/// Report all the problems found in `files`, and exit with a non-zero status if there is any.
+fn check(files: &[&str]) {
+    let mut problems = 0;
+    for file in files {
+        let errors = RfsConfig::check(file);
+        if errors.is_empty() {
+            println!("{}: ok", file);
+        }
+        for e in &errors {
+            println!("{}", e);
+        }
+        problems += errors.len();
+    }
+    if problems > 0 {
+        eprintln!("{} problem(s) found", problems);
+        process::exit(FAILURE);
+    }
+}
+
+/// Apply `change` to the configuration file `file`, then check the result before saving it: the
+/// change is refused if the file would have more problems than before. Prints the entry returned
+/// by `change`.
+fn edit<F, E>(file: &str, change: F)
+where
+    F: FnOnce(&mut ConfigFile) -> Result<String, E>,
+    E: std::fmt::Display,
+{
+    let mut config_file = ConfigFile::open(file).unwrap_or_else(|e| fail(&e.to_string()));
+    let problems_before = config_file.problems().len();
+    let entry = change(&mut config_file).unwrap_or_else(|e| fail(&e.to_string()));
+    let problems = config_file.problems();
+    if problems.len() > problems_before {
+        for e in &problems {
+            eprintln!("{}", e);
+        }
+        fail(&format!("{} not changed: the change makes it invalid", file));
+    }
+    config_file.save().unwrap_or_else(|e| fail(&e.to_string()));
+    print!("{}", entry);
+    for e in RfsConfig::check(file) {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+fn key(length: usize) -> BlowfishKey {
+    generate_key(length).unwrap_or_else(|e| fail(&format!("Can not generate a key: {}", e)))
+}
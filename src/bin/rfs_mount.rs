@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate log;
+use getopts::Options;
+use rfs::cli::{fail, start_logger, DEFAULT_CLIENT_NAME, DEFAULT_CONFIG_PATH,
+               DEFAULT_SERVER_NAME, USAGE_ERROR};
+use rfs::config::RfsConfig;
+use rfs::rfs_client::{Client, RfsClientSession};
+use rfs::rfs_fuse::RfsFilesystem;
+use std::env;
+use std::ffi::OsStr;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt("c", "config", "configuration file", "FILE");
+    opts.optopt("s", "server", "name of the server in the configuration", "NAME");
+    opts.optopt("n", "client", "name of this client in the configuration", "NAME");
+    opts.optopt("l", "log-level", "log level, or RUST_LOG-like filters", "LEVEL");
+    opts.optmulti("o", "", "mount options, passed to FUSE", "OPTIONS");
+    opts.optflag("h", "help", "print this help");
+    let usage = format!("Usage: {} [options] <mountpoint>", args[0]);
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}\n{}", e, opts.usage(&usage));
+            process::exit(USAGE_ERROR);
+        }
+    };
+    if matches.opt_present("h") {
+        print!("{}", opts.usage(&usage));
+        return;
+    }
+    if matches.free.len() != 1 {
+        eprint!("{}", opts.usage(&usage));
+        process::exit(USAGE_ERROR);
+    }
+    start_logger(matches.opt_str("l"));
+
+    let config_path = matches.opt_str("c").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+    let config = match RfsConfig::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => fail(&format!("Invalid configuration. Reason: {}", e)),
+    };
+    let server = matches.opt_str("s").unwrap_or(DEFAULT_SERVER_NAME.to_string());
+    let client = matches.opt_str("n").unwrap_or(DEFAULT_CLIENT_NAME.to_string());
+    let mut session = match RfsClientSession::new(server, client, config) {
+        Some(session) => session,
+        None => fail("Could not reach the server"),
+    };
+    if let Err(e) = session.connect() {
+        fail(&format!("Could not connect. Reason: {}", e));
+    }
+
+    let mut mount_options = Vec::new();
+    for options in matches.opt_strs("o") {
+        mount_options.push("-o".to_string());
+        mount_options.push(options);
+    }
+    let mount_options: Vec<&OsStr> = mount_options.iter().map(OsStr::new).collect();
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let filesystem = RfsFilesystem::new(session, uid, gid);
+    let mountpoint = &matches.free[0];
+    info!("Mounting on {}", mountpoint);
+    if let Err(e) = fuse::mount(filesystem, mountpoint, &mount_options) {
+        fail(&format!("Could not mount {}. Reason: {}", mountpoint, e));
+    }
+}
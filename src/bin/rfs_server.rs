@@ -1,25 +1,142 @@
 #[macro_use]
 extern crate log;
-extern crate env_logger;
-extern crate rfs;
+use getopts::Options;
+use rfs::cli::{fail, start_logger, DEFAULT_CONFIG_PATH, DEFAULT_SERVER_NAME, FAILURE,
+               USAGE_ERROR};
 use rfs::rfs_server::*;
 use rfs::config::RfsConfig;
+use rfs::live_config::SessionPolicy;
+use std::env;
+use std::process;
+use std::time::Duration;
 
 fn main() {
-    start_logger();
-    let config = RfsConfig::from("assets/rfs_config");
-    match RfsServer::new(String::from("srv1"), config) {
-        Some(s) => s.listen(),
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt("c", "config", "configuration file", "FILE");
+    opts.optopt("s", "server", "name of the server in the configuration", "NAME");
+    opts.optopt("b", "bind", "listen on ADDRESS instead of the configured one", "ADDRESS:PORT");
+    opts.optopt("l", "log-level", "log level, or RUST_LOG-like filters", "LEVEL");
+    opts.optopt(
+        "",
+        "max-clients",
+        &format!("number of clients served concurrently (default: {})", DEFAULT_MAX_CLIENTS),
+        "N",
+    );
+    opts.optflag("d", "daemon", "run in the background (default: foreground)");
+    opts.optopt("", "pid-file", "write the PID of the daemon to FILE", "FILE");
+    opts.optopt("", "log-file", "write the logs of the daemon to FILE", "FILE");
+    opts.optopt(
+        "",
+        "reload-policy",
+        "sessions of revoked clients after a reload: continue or terminate (default)",
+        "POLICY",
+    );
+    opts.optopt(
+        "",
+        "watch",
+        "reload the configuration when its file changes, checked every SECONDS",
+        "SECONDS",
+    );
+    opts.optflag("h", "help", "print this help");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}\n{}", e, opts.usage(&format!("Usage: {} [options]", args[0])));
+            process::exit(USAGE_ERROR);
+        }
+    };
+    if matches.opt_present("h") {
+        print!("{}", opts.usage(&format!("Usage: {} [options]", args[0])));
+        return;
+    }
+
+    let policy = match matches.opt_str("reload-policy").as_deref() {
+        None | Some("terminate") => SessionPolicy::TerminateRevoked,
+        Some("continue") => SessionPolicy::Continue,
+        Some(p) => {
+            eprintln!("Invalid reload policy {}: expected continue or terminate", p);
+            process::exit(USAGE_ERROR);
+        }
+    };
+    let watch = match matches.opt_str("watch").map(|s| s.parse::<u64>()) {
+        None => None,
+        Some(Ok(seconds)) if seconds > 0 => Some(Duration::from_secs(seconds)),
+        Some(_) => {
+            eprintln!("Invalid watch interval: expected a positive number of seconds");
+            process::exit(USAGE_ERROR);
+        }
+    };
+
+    let max_clients = match matches.opt_str("max-clients").map(|s| s.parse::<usize>()) {
+        None => DEFAULT_MAX_CLIENTS,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            eprintln!("Invalid number of clients: expected a positive number");
+            process::exit(USAGE_ERROR);
+        }
+    };
+
+    let config_path = matches.opt_str("c").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+    let config = match RfsConfig::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => fail(&format!("Invalid configuration. Reason: {}", e)),
+    };
+    if matches.opt_present("d") {
+        daemonize(matches.opt_str("pid-file"), matches.opt_str("log-file"));
+    }
+    start_logger(matches.opt_str("l"));
+
+    let name = matches.opt_str("s").unwrap_or(DEFAULT_SERVER_NAME.to_string());
+    let server = match matches.opt_str("b") {
+        Some(socket) => RfsServer::bound_to(name, config, socket),
+        None => RfsServer::new(name, config),
+    };
+    match server {
+        Some(mut s) => {
+            s.set_max_clients(max_clients);
+            s.get_config().set_policy(policy);
+            if let Some(interval) = watch {
+                s.get_config().watch(&config_path, interval);
+            }
+            #[cfg(unix)]
+            {
+                if let Err(e) = s.get_config().reload_on_sighup(&config_path) {
+                    warn!("Can not reload the configuration on SIGHUP. Reason: {}", e);
+                }
+            }
+            s.listen()
+        }
         None => {
-            print!("Error");
-            ()
+            error!("Could not start the server");
+            process::exit(FAILURE);
         }
     }
 }
 
-fn start_logger() {
-    match env_logger::init() {
-        Ok(()) => info!("Logger started"),
-        Err(e) => print!("Error during logger initialisation. Reason: {}", e),
+/// Detach from the terminal. Must be called before any thread is started, since threads do not
+/// survive the fork.
+#[cfg(unix)]
+fn daemonize(pid_file: Option<String>, log_file: Option<String>) {
+    use daemonize::Daemonize;
+    use std::fs::OpenOptions;
+
+    let mut daemon = Daemonize::new().working_directory(".");
+    if let Some(pid_file) = pid_file {
+        daemon = daemon.pid_file(pid_file);
+    }
+    if let Some(log_file) = log_file {
+        match OpenOptions::new().create(true).append(true).open(&log_file) {
+            Ok(file) => daemon = daemon.stderr(file),
+            Err(e) => fail(&format!("Can not open {}. Reason: {}", log_file, e)),
+        }
+    }
+    if let Err(e) = daemon.start() {
+        fail(&format!("Can not run in the background. Reason: {}", e));
     }
 }
+
+#[cfg(not(unix))]
+fn daemonize(_: Option<String>, _: Option<String>) {
+    fail("Running in the background is only supported on Unix");
+}
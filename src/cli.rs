@@ -0,0 +1,36 @@
+//! This module holds what the command-line tools share: the default configuration file and
+//! names, the exit statuses, and the start of the logger.
+
+use env_logger::LogBuilder;
+use std::env;
+use std::process;
+
+/// The configuration file used when none is given on the command line.
+pub const DEFAULT_CONFIG_PATH: &str = "assets/rfs_config";
+/// The name of the server used when none is given on the command line.
+pub const DEFAULT_SERVER_NAME: &str = "srv1";
+/// The name of the client used when none is given on the command line.
+pub const DEFAULT_CLIENT_NAME: &str = "cli1";
+
+/// Exit status when a command fails.
+pub const FAILURE: i32 = 1;
+/// Exit status when the command line is invalid.
+pub const USAGE_ERROR: i32 = 2;
+
+/// Print `reason` on the standard error, and exit with the status `FAILURE`.
+pub fn fail(reason: &str) -> ! {
+    eprintln!("{}", reason);
+    process::exit(FAILURE);
+}
+
+/// Start the logger with `level` if given, or with the filters of `RUST_LOG` otherwise.
+pub fn start_logger(level: Option<String>) {
+    let mut builder = LogBuilder::new();
+    if let Some(filters) = level.or(env::var("RUST_LOG").ok()) {
+        builder.parse(&filters);
+    }
+    match builder.init() {
+        Ok(()) => info!("Logger started"),
+        Err(e) => print!("Error during logger initialisation. Reason: {}", e),
+    }
+}
@@ -1,37 +1,230 @@
 //! This module defines configuration related structure and functions. Basically, our configuration
 //! is just a list of clients and servers with relevant details (keys, address, etc..).
 
-use base64;
-use rfs_common::{Identity, Named, BlowfishKey, get_buf_reader};
+use crate::message::MessageType;
+use crate::rfs_common::{Identity, Named, BlowfishKey, MIN_KEY_LENGTH, MAX_KEY_LENGTH};
 use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::fs::File;
-use std::io::BufRead;
+use std::io::{Error as IoError, Read};
+use std::net::ToSocketAddrs;
 use std::path::Path;
 use std::error::Error;
+use toml::Spanned;
 
 /// Fields of the config, either a client or a server.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Field {
-    Client { name: String, key: BlowfishKey },
+    Client {
+        name: String,
+        key: BlowfishKey,
+        root: Option<String>,
+        permissions: Permissions,
+    },
     Server {
         name: String,
         key: BlowfishKey,
         address: String,
         port: String,
+        tls: Option<TlsFiles>,
+        root: Option<String>,
     },
 }
 
+/// Access granted to a client on the files it can reach.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Permissions of a client, enforced by the server on each request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Permissions {
+    access: Access,
+    prefixes: Vec<String>,
+    operations: Option<Vec<MessageType>>,
+}
+
+impl Permissions {
+    /// Create permissions granting `access` to the paths under `prefixes` (relative to the root
+    /// of the client, all paths if empty), for the requests of type `operations` (all requests if
+    /// `None`).
+    pub fn new(
+        access: Access,
+        prefixes: Vec<String>,
+        operations: Option<Vec<MessageType>>,
+    ) -> Self {
+        Permissions {
+            access: access,
+            prefixes: prefixes,
+            operations: operations,
+        }
+    }
+
+    pub fn get_access(&self) -> Access {
+        self.access
+    }
+
+    /// Returns the paths the client can access. If empty, the client can access all the paths
+    /// under its root.
+    pub fn get_prefixes(&self) -> &Vec<String> {
+        &self.prefixes
+    }
+
+    /// Returns the requests the client can send, or `None` if it can send any request.
+    pub fn get_operations(&self) -> Option<&Vec<MessageType>> {
+        self.operations.as_ref()
+    }
+
+    /// Returns whether the client can send requests of type `message_type`. Requests on open
+    /// files are allowed as the matching requests on paths. `Open` and `Close` are always
+    /// allowed: whether a file can be opened depends on its options, see
+    /// `Permissions::allows_open`.
+    pub fn allows(&self, message_type: MessageType) -> bool {
+        let message_type = match message_type {
+            MessageType::ReadHandle => MessageType::ReadFile,
+            MessageType::WriteHandle => MessageType::WriteFile,
+            MessageType::StatHandle => MessageType::Stat,
+            MessageType::TruncateHandle => MessageType::Truncate,
+            MessageType::Open | MessageType::Close => return true,
+            t => t,
+        };
+        let read_only = matches!(
+            message_type,
+            MessageType::ReadFile | MessageType::Stat | MessageType::ListDir
+        );
+        let operation = match self.operations {
+            Some(ref operations) => operations.contains(&message_type),
+            None => true,
+        };
+        operation && (read_only || self.access == Access::ReadWrite)
+    }
+
+    /// Returns whether the client can open a file for reading if `read`, for writing if
+    /// `write`, and truncate it if `truncate`.
+    pub fn allows_open(&self, read: bool, write: bool, truncate: bool) -> bool {
+        (!read || self.allows(MessageType::ReadFile)) &&
+            (!write || self.allows(MessageType::WriteFile)) &&
+            (!truncate || self.allows(MessageType::Truncate))
+    }
+}
+
+impl Default for Permissions {
+    /// Read-write access to all paths, for all requests.
+    fn default() -> Self {
+        Permissions::new(Access::ReadWrite, Vec::new(), None)
+    }
+}
+
+/// Paths of the PEM files used by a server to serve its clients over TLS. Clients trust the
+/// certificates of the same file to authenticate the server.
+#[derive(Clone, PartialEq)]
+pub struct TlsFiles {
+    certificates: String,
+    private_key: String,
+}
+
+impl TlsFiles {
+    pub fn new(certificates: String, private_key: String) -> Self {
+        TlsFiles {
+            certificates: certificates,
+            private_key: private_key,
+        }
+    }
+
+    /// Returns the path of the certificate chain of the server.
+    pub fn get_certificates(&self) -> &String {
+        &self.certificates
+    }
+
+    /// Returns the path of the private key of the server.
+    pub fn get_private_key(&self) -> &String {
+        &self.private_key
+    }
+}
+
+impl Field {
+    /// Returns the TLS files of a server, or `None` for clients and servers serving in clear.
+    pub fn get_tls(&self) -> Option<&TlsFiles> {
+        match *self {
+            Field::Client {
+                name: _,
+                key: _,
+                root: _,
+                permissions: _,
+            } => None,
+            Field::Server {
+                name: _,
+                key: _,
+                address: _,
+                port: _,
+                ref tls,
+                root: _,
+            } => tls.as_ref(),
+        }
+    }
+
+    /// Returns the permissions of a client, or `None` for servers.
+    pub fn get_permissions(&self) -> Option<&Permissions> {
+        match *self {
+            Field::Client {
+                name: _,
+                key: _,
+                root: _,
+                ref permissions,
+            } => Some(permissions),
+            Field::Server {
+                name: _,
+                key: _,
+                address: _,
+                port: _,
+                tls: _,
+                root: _,
+            } => None,
+        }
+    }
+
+    /// Returns the exported directory. For a server, it is the directory it exports; for a
+    /// client, it is a subdirectory of the one exported by the server, to which the client is
+    /// confined.
+    pub fn get_root(&self) -> Option<&String> {
+        match *self {
+            Field::Client {
+                name: _,
+                key: _,
+                ref root,
+                permissions: _,
+            } |
+            Field::Server {
+                name: _,
+                key: _,
+                address: _,
+                port: _,
+                tls: _,
+                ref root,
+            } => root.as_ref(),
+        }
+    }
+}
+
 impl Named for Field {
     type Name = String;
     fn get_name(&self) -> &Self::Name {
-        match self {
-            &Field::Client { ref name, key: _ } => name,
-            &Field::Server {
+        match *self {
+            Field::Client {
+                ref name,
+                key: _,
+                root: _,
+                permissions: _,
+            } => name,
+            Field::Server {
                 ref name,
                 key: _,
                 address: _,
                 port: _,
+                tls: _,
+                root: _,
             } => name,
         }
     }
@@ -39,13 +232,20 @@ impl Named for Field {
 
 impl Identity for Field {
     fn get_secret(&self) -> &BlowfishKey {
-        match self {
-            &Field::Client { name: _, ref key } => key,
-            &Field::Server {
+        match *self {
+            Field::Client {
+                name: _,
+                ref key,
+                root: _,
+                permissions: _,
+            } => key,
+            Field::Server {
                 name: _,
                 ref key,
                 address: _,
                 port: _,
+                tls: _,
+                root: _,
             } => key,
         }
     }
@@ -57,6 +257,7 @@ pub trait Config {
     type Field: Named;
     type ErrorType: Error;
     fn get_from_name(&self, name: Self::Name) -> Result<&Self::Field, Self::ErrorType>;
+    #[allow(clippy::result_unit_err)]
     fn add_field(&mut self, f: Self::Field) -> Result<(), ()>;
 }
 
@@ -64,23 +265,142 @@ pub trait Config {
 #[derive(Clone)]
 pub struct RfsConfig {
     fields: HashMap<String, Field>,
+    encryption: bool,
 }
 
 impl RfsConfig {
-    /// Construct a new empty configuration.
+    /// Construct a new empty configuration. Frames are encrypted by default.
     pub fn new() -> Self {
-        RfsConfig { fields: HashMap::new() }
+        RfsConfig {
+            fields: HashMap::new(),
+            encryption: true,
+        }
+    }
+
+    /// Read the configuration file `path`, either in TOML format (see `TomlConfig`) or in the
+    /// legacy format (see `from_legacy`). Files whose extension is `toml`, or whose first line
+    /// which is neither empty nor a comment is a table header (e.g. `[[server]]`), are read as
+    /// TOML.
+    ///
+    /// Fails on the first problem found in the file, so that a typo does not go unnoticed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RfsConfig, RfsConfigError> {
+        let (conf, errors) = RfsConfig::parse(path.as_ref());
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(conf),
+        }
+    }
+
+    /// Check the configuration file `path`, and returns all the problems found: those which make
+    /// `RfsConfig::load` fail, and also servers whose address can not be resolved or which do not
+    /// name the directory they export.
+    pub fn check<P: AsRef<Path>>(path: P) -> Vec<RfsConfigError> {
+        let file = path.as_ref().display().to_string();
+        let (conf, mut errors) = RfsConfig::parse(path.as_ref());
+        let mut names: Vec<&String> = conf.fields.keys().collect();
+        names.sort();
+        for name in names {
+            if let Field::Server {
+                name: _,
+                key: _,
+                ref address,
+                ref port,
+                tls: _,
+                ref root,
+            } = conf.fields[name]
+            {
+                let socket = socket_address(address, port);
+                match socket.to_socket_addrs().map(|mut a| a.next()) {
+                    Ok(Some(_)) => (),
+                    _ => {
+                        errors.push(RfsConfigError::new(RfsConfigErrorKind::UnresolvableAddress {
+                            file: file.clone(),
+                            name: name.clone(),
+                            address: socket,
+                        }))
+                    }
+                }
+                if root.is_none() {
+                    errors.push(RfsConfigError::new(RfsConfigErrorKind::NoExportRoot {
+                        file: file.clone(),
+                        name: name.clone(),
+                    }));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Read the configuration file `path`. Returns the valid entries, and the problems found in
+    /// the other ones.
+    fn parse(path: &Path) -> (RfsConfig, Vec<RfsConfigError>) {
+        let file = path.display().to_string();
+        let mut content = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+            Ok(_) => RfsConfig::parse_content(path, &content),
+            Err(e) => {
+                let kind = RfsConfigErrorKind::Io {
+                    file: file,
+                    error: e,
+                };
+                (RfsConfig::new(), vec![RfsConfigError::new(kind)])
+            }
+        }
+    }
+
+    /// Read `content`, the content of the configuration file `path`. Returns the valid entries,
+    /// and the problems found in the other ones.
+    pub(crate) fn parse_content(path: &Path, content: &str) -> (RfsConfig, Vec<RfsConfigError>) {
+        let file = path.display().to_string();
+        if is_toml(path, content) {
+            from_toml(content, &file)
+        } else {
+            from_legacy(content, &file)
+        }
+    }
+
+    /// Add the entry `f`, read on `line` of `file`. Fails if its name is already taken, in which
+    /// case the first entry is kept.
+    fn add(&mut self, f: Field, file: &str, line: usize) -> Result<(), RfsConfigError> {
+        if self.fields.contains_key(f.get_name()) {
+            return Err(RfsConfigError::new(RfsConfigErrorKind::DuplicateName {
+                file: String::from(file),
+                line: line,
+                name: f.get_name().clone(),
+            }));
+        }
+        self.fields.insert(f.get_name().clone(), f);
+        Ok(())
+    }
+
+    /// Returns whether the frames exchanged after the handshake are encrypted. Clients ask the
+    /// server for encryption if enabled, and servers reject the clients which do not ask for it if
+    /// enabled: the handshake fails unless clients and servers agree on this option.
+    pub fn is_encryption_enabled(&self) -> bool {
+        self.encryption
+    }
+
+    /// Enable or disable the encryption of the frames exchanged after the handshake.
+    pub fn set_encryption(&mut self, encryption: bool) {
+        self.encryption = encryption;
     }
 
     pub fn get_server_address(&self, server_name: String) -> Option<String> {
         match self.get_from_name(server_name) {
-            Ok(&Field::Client { name: _, key: _ }) => None,
-            Ok(&Field::Server {
+            Ok(Field::Client {
+                   name: _,
+                   key: _,
+                   root: _,
+                   permissions: _,
+               }) => None,
+            Ok(Field::Server {
                    name: _,
                    key: _,
-                   ref address,
-                   ref port,
-               }) => Some(address.clone() + ":" + &port),
+                   address,
+                   port,
+                   tls: _,
+                   root: _,
+               }) => Some(socket_address(address, port)),
             Err(e) => {
                 warn!{"Can not retrieve server address. Reason: {}", e};
                 None
@@ -89,6 +409,12 @@ impl RfsConfig {
     }
 }
 
+impl Default for RfsConfig {
+    fn default() -> Self {
+        RfsConfig::new()
+    }
+}
+
 impl Config for RfsConfig {
     type Name = String;
     type Field = Field;
@@ -108,77 +434,470 @@ impl Config for RfsConfig {
     }
 }
 
+/// Read a configuration file, keeping the entries which are valid. Problems are logged, and
+/// invalid entries ignored; use `RfsConfig::load` to fail on any problem instead.
 impl<T: AsRef<Path> + Display + Clone> From<T> for RfsConfig {
     fn from(p: T) -> Self {
-        match File::open(p.clone()) {
-            Ok(file) => {
-                let mut conf = RfsConfig::new();
-                let mut line_nb = 1;
-                for line in get_buf_reader(file).lines() {
-                    match line {
-                        Ok(l) => {
-                            let elem: Vec<&str> = l.split(':').collect();
-                            match elem[0] {
-                                "server" => {
-                                    if elem.len() >= 5 {
-                                        let new_field = Field::Server {
-                                            name: String::from(elem[1]),
-                                            key: base64::decode(elem[2]).unwrap(),
-                                            address: String::from(elem[3]),
-                                            port: String::from(elem[4]),
-                                        };
-                                        match conf.add_field(new_field) {
-                                            Err(()) => warn!("Duplicate name \"{}\".", elem[1]),
-                                            _ => (),
-                                        }
-                                    } else {
-                                        warn!(
-                                            "Line {} of file {} does not contain enough fields.",
-                                            line_nb,
-                                            p
-                                        );
-                                    }
-                                }
-
-                                "client" => {
-                                    if elem.len() >= 3 {
-                                        let new_field = Field::Client {
-                                            name: String::from(elem[1]),
-                                            key: base64::decode(elem[2]).unwrap(),
-                                        };
-                                        match conf.add_field(new_field) {
-                                            Err(()) => warn!("Duplicate name \"{}\".", elem[1]),
-                                            _ => (),
-                                        }
-                                    } else {
-                                        warn!(
-                                            "Line {} of file {} does not contain enough fields.",
-                                            line_nb,
-                                            p
-                                        );
-                                    }
-                                }
-
-                                _ => (),
-                            }
-                        }
-                        Err(e) => warn!("Can't read line {} of file {}. Reason: {}", line_nb, p, e),
+        let (conf, errors) = RfsConfig::parse(p.as_ref());
+        for e in errors {
+            warn!("Ignoring invalid configuration. Reason: {}", e);
+        }
+        conf
+    }
+}
+
+/// Returns whether the file `path`, whose content is `content`, is in TOML format.
+pub(crate) fn is_toml(path: &Path, content: &str) -> bool {
+    if path.extension().is_some_and(|extension| extension == "toml") {
+        return true;
+    }
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .is_some_and(|line| line.starts_with('['))
+}
+
+/// Read a configuration in the legacy format, made of lines of colon separated fields:
+///
+/// - `client:<name>:<key>[:<root>[:<ro|rw>[:<prefixes>[:<operations>]]]]`
+/// - `server:<name>:<key>:<address>:<port>[:<certificates>:<private key>[:<root>]]`
+/// - `option:encryption:<on|off>`
+///
+/// Keys are base64 encoded. Prefixes and operations (`read`, `write`, `stat`, `list`, `mkdir`,
+/// `rmdir`, `unlink`, `rename`, `truncate`, `settimes`) are comma separated. Optional fields may
+/// be left empty. Empty lines and lines starting with `#` are ignored. Since fields are separated
+/// by colons, IPv6 addresses can not be used in this format.
+///
+/// Returns the valid entries, and the problems found on the other lines.
+fn from_legacy(content: &str, file: &str) -> (RfsConfig, Vec<RfsConfigError>) {
+    let mut conf = RfsConfig::new();
+    let mut errors = Vec::new();
+    for (index, l) in content.lines().enumerate() {
+        let line = index + 1;
+        let elem: Vec<&str> = l.split(':').collect();
+        let added = match elem[0] {
+            "server" => legacy_server(&elem, file, line).and_then(|f| conf.add(f, file, line)),
+            "client" => legacy_client(&elem, file, line).and_then(|f| conf.add(f, file, line)),
+            "option" => {
+                match (elem.get(1), elem.get(2)) {
+                    (Some(&"encryption"), Some(&"on")) => {
+                        conf.set_encryption(true);
+                        Ok(())
+                    }
+                    (Some(&"encryption"), Some(&"off")) => {
+                        conf.set_encryption(false);
+                        Ok(())
                     }
-                    line_nb = line_nb + 1;
+                    _ => Err(RfsConfigError::invalid_value(file, line, &elem[1..].join(":"))),
                 }
-                conf
-            }
-            Err(e) => {
-                warn!("Could not open file {}. Reason: {}", p, e);
-                RfsConfig::new()
             }
+            _ if l.trim().is_empty() || l.starts_with('#') => Ok(()),
+            entry => Err(RfsConfigError::invalid_value(file, line, entry)),
+        };
+        if let Err(e) = added {
+            errors.push(e);
+        }
+    }
+    (conf, errors)
+}
+
+/// Read the line `server:<name>:<key>:<address>:<port>[:<certificates>:<private key>[:<root>]]`.
+fn legacy_server(elem: &[&str], file: &str, line: usize) -> Result<Field, RfsConfigError> {
+    if elem.len() < 5 {
+        return Err(RfsConfigError::missing_field(file, line, LEGACY_SERVER_FIELDS[elem.len()]));
+    }
+    let tls = match (optional_field(elem, 5), optional_field(elem, 6)) {
+        (Some(certificates), Some(private_key)) => Some(TlsFiles::new(certificates, private_key)),
+        (Some(_), None) => return Err(RfsConfigError::missing_field(file, line, "private key")),
+        (None, Some(_)) => return Err(RfsConfigError::missing_field(file, line, "certificates")),
+        (None, None) => None,
+    };
+    Ok(Field::Server {
+        name: String::from(elem[1]),
+        key: decode_key(elem[1], elem[2], file, line)?,
+        address: String::from(elem[3]),
+        port: parse_port(elem[4], file, line)?,
+        tls: tls,
+        root: optional_field(elem, 7),
+    })
+}
+
+/// Read the line `client:<name>:<key>[:<root>[:<ro|rw>[:<prefixes>[:<operations>]]]]`.
+fn legacy_client(elem: &[&str], file: &str, line: usize) -> Result<Field, RfsConfigError> {
+    if elem.len() < 3 {
+        return Err(RfsConfigError::missing_field(file, line, LEGACY_CLIENT_FIELDS[elem.len()]));
+    }
+    Ok(Field::Client {
+        name: String::from(elem[1]),
+        key: decode_key(elem[1], elem[2], file, line)?,
+        root: optional_field(elem, 3),
+        permissions: permissions_fields(elem, 4, file, line)?,
+    })
+}
+
+/// Names of the mandatory fields of the legacy format, in order.
+const LEGACY_SERVER_FIELDS: [&str; 5] = ["entry", "name", "key", "address", "port"];
+const LEGACY_CLIENT_FIELDS: [&str; 3] = ["entry", "name", "key"];
+
+/// A configuration file in TOML format, e.g.:
+///
+/// ```toml
+/// [options]
+/// encryption = true
+///
+/// [[server]]
+/// name = "srv1"
+/// key = "zyxwvu"
+/// address = "::1"
+/// port = 4242
+/// certificates = "srv1.crt" # optional, with private_key
+/// private_key = "srv1.key"  # optional, with certificates
+/// root = "/srv/rfs"         # optional
+///
+/// [[client]]
+/// name = "cli1"
+/// key = "123456"
+/// root = "cli1"                  # optional
+/// access = "ro"                  # optional, "ro" or "rw"
+/// prefixes = ["docs"]            # optional
+/// operations = ["read", "list"]  # optional
+/// ```
+///
+/// Keys are base64 encoded. Unknown tables and keys are rejected.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlConfig {
+    #[serde(default)]
+    options: TomlOptions,
+    #[serde(default)]
+    server: Vec<TomlServer>,
+    #[serde(default)]
+    client: Vec<TomlClient>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlOptions {
+    encryption: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlServer {
+    name: Spanned<String>,
+    key: Spanned<String>,
+    address: String,
+    port: Spanned<i64>,
+    certificates: Option<String>,
+    private_key: Option<String>,
+    root: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlClient {
+    name: Spanned<String>,
+    key: Spanned<String>,
+    root: Option<String>,
+    access: Option<String>,
+    #[serde(default)]
+    prefixes: Vec<String>,
+    operations: Option<Vec<String>>,
+}
+
+/// Read a configuration in TOML format (see `TomlConfig`). Returns the valid entries, and the
+/// problems found in the other ones. If the file is not valid TOML, the configuration is empty.
+fn from_toml(content: &str, file: &str) -> (RfsConfig, Vec<RfsConfigError>) {
+    let mut conf = RfsConfig::new();
+    let mut errors = Vec::new();
+    let toml_config: TomlConfig = match toml::from_str(content) {
+        Ok(toml_config) => toml_config,
+        Err(e) => {
+            let line = e.line_col().map(|(line, _)| line + 1);
+            errors.push(RfsConfigError::new(RfsConfigErrorKind::Syntax {
+                file: String::from(file),
+                line: line,
+                reason: e.to_string(),
+            }));
+            return (conf, errors);
+        }
+    };
+    if let Some(encryption) = toml_config.options.encryption {
+        conf.set_encryption(encryption);
+    }
+    for server in toml_config.server {
+        let line = line_of(content, server.name.start());
+        if let Err(e) = toml_server(server, content, file).and_then(|f| conf.add(f, file, line)) {
+            errors.push(e);
+        }
+    }
+    for client in toml_config.client {
+        let line = line_of(content, client.name.start());
+        if let Err(e) = toml_client(client, content, file).and_then(|f| conf.add(f, file, line)) {
+            errors.push(e);
+        }
+    }
+    (conf, errors)
+}
+
+fn toml_server(server: TomlServer, content: &str, file: &str) -> Result<Field, RfsConfigError> {
+    let line = line_of(content, server.name.start());
+    let tls = match (server.certificates, server.private_key) {
+        (Some(certificates), Some(private_key)) => Some(TlsFiles::new(certificates, private_key)),
+        (Some(_), None) => return Err(RfsConfigError::missing_field(file, line, "private_key")),
+        (None, Some(_)) => return Err(RfsConfigError::missing_field(file, line, "certificates")),
+        (None, None) => None,
+    };
+    let key_line = line_of(content, server.key.start());
+    let port_line = line_of(content, server.port.start());
+    Ok(Field::Server {
+        key: decode_key(server.name.get_ref(), server.key.get_ref(), file, key_line)?,
+        name: server.name.into_inner(),
+        address: server.address,
+        port: parse_port(&server.port.get_ref().to_string(), file, port_line)?,
+        tls: tls,
+        root: server.root,
+    })
+}
+
+fn toml_client(client: TomlClient, content: &str, file: &str) -> Result<Field, RfsConfigError> {
+    let line = line_of(content, client.name.start());
+    let key_line = line_of(content, client.key.start());
+    let operations = match client.operations {
+        Some(operations) => {
+            Some(operations_of_names(
+                operations.iter().map(String::as_str),
+                file,
+                line,
+            )?)
+        }
+        None => None,
+    };
+    Ok(Field::Client {
+        key: decode_key(client.name.get_ref(), client.key.get_ref(), file, key_line)?,
+        name: client.name.into_inner(),
+        root: client.root,
+        permissions: Permissions::new(
+            access_of_name(client.access.as_deref(), file, line)?,
+            client.prefixes,
+            operations,
+        ),
+    })
+}
+
+/// Returns the number of the line at byte `offset` of `content`, starting from 1.
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+/// Decode the base64 `key` of the entry `name`, which must be a valid Blowfish key.
+fn decode_key(
+    name: &str,
+    key: &str,
+    file: &str,
+    line: usize,
+) -> Result<BlowfishKey, RfsConfigError> {
+    let key = base64::decode(key).map_err(|e| {
+        RfsConfigError::new(RfsConfigErrorKind::BadKey {
+            file: String::from(file),
+            line: line,
+            name: String::from(name),
+            reason: e.to_string(),
+        })
+    })?;
+    if key.len() < MIN_KEY_LENGTH || key.len() > MAX_KEY_LENGTH {
+        return Err(RfsConfigError::new(RfsConfigErrorKind::BadKeyLength {
+            file: String::from(file),
+            line: line,
+            name: String::from(name),
+            length: key.len(),
+        }));
+    }
+    Ok(key)
+}
+
+/// Check that `port` is a valid TCP port, i.e. in `1..65536`.
+pub(crate) fn parse_port(port: &str, file: &str, line: usize) -> Result<String, RfsConfigError> {
+    match port.parse::<u16>() {
+        Ok(p) if p != 0 => Ok(p.to_string()),
+        _ => {
+            Err(RfsConfigError::new(RfsConfigErrorKind::InvalidPort {
+                file: String::from(file),
+                line: line,
+                port: String::from(port),
+            }))
         }
     }
 }
 
+/// Returns `address:port`, with IPv6 addresses enclosed in brackets.
+fn socket_address(address: &str, port: &str) -> String {
+    if address.contains(':') && !address.starts_with('[') {
+        format!("[{}]:{}", address, port)
+    } else {
+        format!("{}:{}", address, port)
+    }
+}
+
+/// Returns the field at `index` of a line, or `None` if it is missing or empty.
+fn optional_field(elem: &[&str], index: usize) -> Option<String> {
+    match elem.get(index) {
+        Some(f) if !f.is_empty() => Some(String::from(*f)),
+        _ => None,
+    }
+}
+
+/// Returns the request type named `name` in a config file.
+fn operation_of_name(name: &str) -> Option<MessageType> {
+    match name {
+        "write" => Some(MessageType::WriteFile),
+        "read" => Some(MessageType::ReadFile),
+        "stat" => Some(MessageType::Stat),
+        "list" => Some(MessageType::ListDir),
+        "mkdir" => Some(MessageType::Mkdir),
+        "rmdir" => Some(MessageType::Rmdir),
+        "unlink" => Some(MessageType::Unlink),
+        "rename" => Some(MessageType::Rename),
+        "truncate" => Some(MessageType::Truncate),
+        "settimes" => Some(MessageType::SetTimes),
+        _ => None,
+    }
+}
+
+/// Returns the name of the request type `operation` in a config file, or `None` for replies.
+pub(crate) fn name_of_operation(operation: MessageType) -> Option<&'static str> {
+    match operation {
+        MessageType::WriteFile => Some("write"),
+        MessageType::ReadFile => Some("read"),
+        MessageType::Stat => Some("stat"),
+        MessageType::ListDir => Some("list"),
+        MessageType::Mkdir => Some("mkdir"),
+        MessageType::Rmdir => Some("rmdir"),
+        MessageType::Unlink => Some("unlink"),
+        MessageType::Rename => Some("rename"),
+        MessageType::Truncate => Some("truncate"),
+        MessageType::SetTimes => Some("settimes"),
+        _ => None,
+    }
+}
+
+/// Returns the access named `name` (`ro` or `rw`) in a config file. A missing access grants read
+/// and write.
+fn access_of_name(name: Option<&str>, file: &str, line: usize) -> Result<Access, RfsConfigError> {
+    match name {
+        None | Some("rw") => Ok(Access::ReadWrite),
+        Some("ro") => Ok(Access::ReadOnly),
+        Some(a) => Err(RfsConfigError::invalid_value(file, line, a)),
+    }
+}
+
+/// Returns the request types named `names` in a config file.
+fn operations_of_names<'a, I>(
+    names: I,
+    file: &str,
+    line: usize,
+) -> Result<Vec<MessageType>, RfsConfigError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    names
+        .map(|name| {
+            operation_of_name(name).ok_or(RfsConfigError::invalid_value(file, line, name))
+        })
+        .collect()
+}
+
+/// Reads the permissions of a client from the fields `<access>:<prefixes>:<operations>` starting
+/// at `index`, where prefixes and operations are comma separated. Missing or empty fields grant
+/// everything.
+fn permissions_fields(
+    elem: &[&str],
+    index: usize,
+    file: &str,
+    line: usize,
+) -> Result<Permissions, RfsConfigError> {
+    let access = access_of_name(elem.get(index).cloned().filter(|a| !a.is_empty()), file, line)?;
+    let prefixes = match optional_field(elem, index + 1) {
+        Some(prefixes) => {
+            prefixes
+                .split(',')
+                .filter(|prefix| !prefix.is_empty())
+                .map(String::from)
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let operations = match optional_field(elem, index + 2) {
+        Some(operations) => Some(operations_of_names(operations.split(','), file, line)?),
+        None => None,
+    };
+    Ok(Permissions::new(access, prefixes, operations))
+}
+
 #[derive(Debug)]
 enum RfsConfigErrorKind {
     NoSuchName { name: String },
+    Io { file: String, error: IoError },
+    Syntax {
+        file: String,
+        line: Option<usize>,
+        reason: String,
+    },
+    BadKey {
+        file: String,
+        line: usize,
+        name: String,
+        reason: String,
+    },
+    BadKeyLength {
+        file: String,
+        line: usize,
+        name: String,
+        length: usize,
+    },
+    MissingField {
+        file: String,
+        line: usize,
+        field: &'static str,
+    },
+    DuplicateName {
+        file: String,
+        line: usize,
+        name: String,
+    },
+    InvalidPort {
+        file: String,
+        line: usize,
+        port: String,
+    },
+    InvalidValue {
+        file: String,
+        line: usize,
+        value: String,
+    },
+    UnresolvableAddress {
+        file: String,
+        name: String,
+        address: String,
+    },
+    NoExportRoot { file: String, name: String },
+}
+
+/// The kind of problem reported by a `RfsConfigError`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RfsConfigProblem {
+    NoSuchName,
+    Io,
+    Syntax,
+    BadKey,
+    BadKeyLength,
+    MissingField,
+    DuplicateName,
+    InvalidPort,
+    InvalidValue,
+    UnresolvableAddress,
+    NoExportRoot,
 }
 
 #[derive(Debug)]
@@ -186,18 +905,353 @@ pub struct RfsConfigError {
     kind: RfsConfigErrorKind,
 }
 
+impl RfsConfigError {
+    fn new(kind: RfsConfigErrorKind) -> Self {
+        RfsConfigError { kind: kind }
+    }
+
+    fn missing_field(file: &str, line: usize, field: &'static str) -> Self {
+        RfsConfigError::new(RfsConfigErrorKind::MissingField {
+            file: String::from(file),
+            line: line,
+            field: field,
+        })
+    }
+
+    fn invalid_value(file: &str, line: usize, value: &str) -> Self {
+        RfsConfigError::new(RfsConfigErrorKind::InvalidValue {
+            file: String::from(file),
+            line: line,
+            value: String::from(value),
+        })
+    }
+
+    /// Returns the kind of problem found.
+    pub fn get_problem(&self) -> RfsConfigProblem {
+        match self.kind {
+            RfsConfigErrorKind::NoSuchName { .. } => RfsConfigProblem::NoSuchName,
+            RfsConfigErrorKind::Io { .. } => RfsConfigProblem::Io,
+            RfsConfigErrorKind::Syntax { .. } => RfsConfigProblem::Syntax,
+            RfsConfigErrorKind::BadKey { .. } => RfsConfigProblem::BadKey,
+            RfsConfigErrorKind::BadKeyLength { .. } => RfsConfigProblem::BadKeyLength,
+            RfsConfigErrorKind::MissingField { .. } => RfsConfigProblem::MissingField,
+            RfsConfigErrorKind::DuplicateName { .. } => RfsConfigProblem::DuplicateName,
+            RfsConfigErrorKind::InvalidPort { .. } => RfsConfigProblem::InvalidPort,
+            RfsConfigErrorKind::InvalidValue { .. } => RfsConfigProblem::InvalidValue,
+            RfsConfigErrorKind::UnresolvableAddress { .. } => {
+                RfsConfigProblem::UnresolvableAddress
+            }
+            RfsConfigErrorKind::NoExportRoot { .. } => RfsConfigProblem::NoExportRoot,
+        }
+    }
+
+    /// Returns the configuration file in which the problem was found, if any.
+    pub fn get_file(&self) -> Option<&str> {
+        match self.kind {
+            RfsConfigErrorKind::NoSuchName { .. } => None,
+            RfsConfigErrorKind::Io { ref file, .. } |
+            RfsConfigErrorKind::Syntax { ref file, .. } |
+            RfsConfigErrorKind::BadKey { ref file, .. } |
+            RfsConfigErrorKind::BadKeyLength { ref file, .. } |
+            RfsConfigErrorKind::MissingField { ref file, .. } |
+            RfsConfigErrorKind::DuplicateName { ref file, .. } |
+            RfsConfigErrorKind::InvalidPort { ref file, .. } |
+            RfsConfigErrorKind::InvalidValue { ref file, .. } |
+            RfsConfigErrorKind::UnresolvableAddress { ref file, .. } |
+            RfsConfigErrorKind::NoExportRoot { ref file, .. } => Some(file),
+        }
+    }
+
+    /// Returns the number of the line, starting from 1, on which the problem was found, if it is
+    /// known.
+    pub fn get_line(&self) -> Option<usize> {
+        match self.kind {
+            RfsConfigErrorKind::Syntax { line, .. } => line,
+            RfsConfigErrorKind::BadKey { line, .. } |
+            RfsConfigErrorKind::BadKeyLength { line, .. } |
+            RfsConfigErrorKind::MissingField { line, .. } |
+            RfsConfigErrorKind::DuplicateName { line, .. } |
+            RfsConfigErrorKind::InvalidPort { line, .. } |
+            RfsConfigErrorKind::InvalidValue { line, .. } => Some(line),
+            _ => None,
+        }
+    }
+}
+
+/// Formats the problem as `<file>:<line>: <problem>`, or without the line if it is not known.
 impl Display for RfsConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RfsConfig Error: {:?}", self.kind)
+        match (self.get_file(), self.get_line()) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: ", file, line)?,
+            (Some(file), None) => write!(f, "{}: ", file)?,
+            (None, _) => (),
+        }
+        match self.kind {
+            RfsConfigErrorKind::NoSuchName { ref name } => {
+                write!(f, "no client or server named `{}`", name)
+            }
+            RfsConfigErrorKind::Io { ref error, .. } => write!(f, "{}", error),
+            RfsConfigErrorKind::Syntax { ref reason, .. } => write!(f, "{}", reason),
+            RfsConfigErrorKind::BadKey {
+                ref name,
+                ref reason,
+                ..
+            } => write!(f, "key of `{}` is not valid base64 ({})", name, reason),
+            RfsConfigErrorKind::BadKeyLength { ref name, length, .. } => {
+                write!(
+                    f,
+                    "key of `{}` is {} bytes long, instead of {} to {}",
+                    name,
+                    length,
+                    MIN_KEY_LENGTH,
+                    MAX_KEY_LENGTH
+                )
+            }
+            RfsConfigErrorKind::MissingField { field, .. } => write!(f, "missing {}", field),
+            RfsConfigErrorKind::DuplicateName { ref name, .. } => {
+                write!(f, "duplicate name `{}`", name)
+            }
+            RfsConfigErrorKind::InvalidPort { ref port, .. } => {
+                write!(f, "invalid port `{}`, instead of 1 to 65535", port)
+            }
+            RfsConfigErrorKind::InvalidValue { ref value, .. } => {
+                write!(f, "invalid value `{}`", value)
+            }
+            RfsConfigErrorKind::UnresolvableAddress {
+                ref name,
+                ref address,
+                ..
+            } => write!(f, "address `{}` of `{}` can not be resolved", address, name),
+            RfsConfigErrorKind::NoExportRoot { ref name, .. } => {
+                write!(f, "server `{}` does not name the directory it exports", name)
+            }
+        }
     }
 }
 
 impl Error for RfsConfigError {
     fn description(&self) -> &str {
         match self.kind {
-            RfsConfigErrorKind::NoSuchName { name: _ } => {
+            RfsConfigErrorKind::NoSuchName { .. } => {
                 "There is no client or serveur with such name in the config."
             }
+            RfsConfigErrorKind::Io { .. } => "The config file can not be read.",
+            RfsConfigErrorKind::Syntax { .. } => "The config file is not well formed.",
+            RfsConfigErrorKind::BadKey { .. } => "A key is not valid base64.",
+            RfsConfigErrorKind::BadKeyLength { .. } => {
+                "A key is too short or too long for Blowfish."
+            }
+            RfsConfigErrorKind::MissingField { .. } => "An entry lacks a mandatory field.",
+            RfsConfigErrorKind::DuplicateName { .. } => {
+                "Several clients or servers have the same name."
+            }
+            RfsConfigErrorKind::InvalidPort { .. } => "A port is not in 1..65536.",
+            RfsConfigErrorKind::InvalidValue { .. } => {
+                "An entry, option, access or operation is unknown."
+            }
+            RfsConfigErrorKind::UnresolvableAddress { .. } => {
+                "The address of a server can not be resolved."
+            }
+            RfsConfigErrorKind::NoExportRoot { .. } => {
+                "A server does not name the directory it exports."
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file: &str, content: &str) -> (RfsConfig, Vec<RfsConfigError>) {
+        RfsConfig::parse_content(Path::new(file), content)
+    }
+
+    fn problems(errors: &[RfsConfigError]) -> Vec<(RfsConfigProblem, Option<usize>)> {
+        errors.iter().map(|e| (e.get_problem(), e.get_line())).collect()
+    }
+
+    #[test]
+    fn reads_legacy_entries() {
+        let content = "# comment\n\
+                       server:srv1:enl4d3Z1:localhost:4242:::/srv/rfs\n\
+                       \n\
+                       client:cli1:MTIzNDU2:cli1:ro:docs,src:read,list\n\
+                       option:encryption:off\n";
+        let (conf, errors) = parse("rfs_config", content);
+        assert!(errors.is_empty());
+        assert!(!conf.is_encryption_enabled());
+        assert_eq!(
+            conf.get_server_address(String::from("srv1")),
+            Some(String::from("localhost:4242"))
+        );
+        let server = conf.get_from_name(String::from("srv1")).unwrap();
+        assert_eq!(server.get_root(), Some(&String::from("/srv/rfs")));
+        assert_eq!(server.get_secret(), b"zyxwvu");
+        let client = conf.get_from_name(String::from("cli1")).unwrap();
+        let permissions = Permissions::new(
+            Access::ReadOnly,
+            vec![String::from("docs"), String::from("src")],
+            Some(vec![MessageType::ReadFile, MessageType::ListDir]),
+        );
+        assert_eq!(client.get_permissions(), Some(&permissions));
+        assert_eq!(client.get_root(), Some(&String::from("cli1")));
+    }
+
+    #[test]
+    fn reports_legacy_problems_with_their_line() {
+        let content = "client:cli1:MTIzNDU2\n\
+                       client:cli1:YWJjZGVm\n\
+                       client:cli2:not base64\n\
+                       client:cli3:MTI=\n\
+                       server:srv1:enl4d3Z1:localhost\n\
+                       server:srv2:enl4d3Z1:localhost:0\n\
+                       client:cli4:MTIzNDU2::xx\n\
+                       clients:cli5\n";
+        let (conf, errors) = parse("rfs_config", content);
+        assert_eq!(
+            problems(&errors),
+            vec![
+                (RfsConfigProblem::DuplicateName, Some(2)),
+                (RfsConfigProblem::BadKey, Some(3)),
+                (RfsConfigProblem::BadKeyLength, Some(4)),
+                (RfsConfigProblem::MissingField, Some(5)),
+                (RfsConfigProblem::InvalidPort, Some(6)),
+                (RfsConfigProblem::InvalidValue, Some(7)),
+                (RfsConfigProblem::InvalidValue, Some(8)),
+            ]
+        );
+        assert_eq!(conf.get_from_name(String::from("cli1")).unwrap().get_secret(), b"123456");
+        assert!(errors.iter().all(|e| e.get_file() == Some("rfs_config")));
+    }
+
+    #[test]
+    fn reads_toml_entries() {
+        let content = "[options]\n\
+                       encryption = false\n\
+                       \n\
+                       [[server]]\n\
+                       name = \"srv1\"\n\
+                       key = \"enl4d3Z1\"\n\
+                       address = \"::1\"\n\
+                       port = 4242\n\
+                       certificates = \"srv1.crt\"\n\
+                       private_key = \"srv1.key\"\n\
+                       \n\
+                       [[client]]\n\
+                       name = \"cli1\"\n\
+                       key = \"MTIzNDU2\"\n\
+                       access = \"ro\"\n\
+                       operations = [\"read\"]\n";
+        let (conf, errors) = parse("rfs.toml", content);
+        assert!(errors.is_empty());
+        assert!(!conf.is_encryption_enabled());
+        assert_eq!(
+            conf.get_server_address(String::from("srv1")),
+            Some(String::from("[::1]:4242"))
+        );
+        let server = conf.get_from_name(String::from("srv1")).unwrap();
+        let tls = TlsFiles::new(String::from("srv1.crt"), String::from("srv1.key"));
+        assert!(server.get_tls() == Some(&tls));
+        let client = conf.get_from_name(String::from("cli1")).unwrap();
+        let permissions =
+            Permissions::new(Access::ReadOnly, Vec::new(), Some(vec![MessageType::ReadFile]));
+        assert_eq!(client.get_permissions(), Some(&permissions));
+    }
+
+    #[test]
+    fn reports_toml_problems_with_their_line() {
+        let content = "[[client]]\n\
+                       name = \"cli1\"\n\
+                       key = \"MTI=\"\n\
+                       \n\
+                       [[client]]\n\
+                       name = \"cli2\"\n\
+                       key = \"MTIzNDU2\"\n\
+                       access = \"rx\"\n\
+                       \n\
+                       [[server]]\n\
+                       name = \"srv1\"\n\
+                       key = \"enl4d3Z1\"\n\
+                       address = \"localhost\"\n\
+                       port = 99999\n";
+        let (_, errors) = parse("rfs.toml", content);
+        assert_eq!(
+            problems(&errors),
+            vec![
+                (RfsConfigProblem::InvalidPort, Some(14)),
+                (RfsConfigProblem::BadKeyLength, Some(3)),
+                (RfsConfigProblem::InvalidValue, Some(6)),
+            ]
+        );
+
+        let (conf, errors) = parse("rfs.toml", "[[client]]\nname = \"cli1\"\nkey = 3\n");
+        assert_eq!(problems(&errors), vec![(RfsConfigProblem::Syntax, Some(3))]);
+        assert!(conf.get_from_name(String::from("cli1")).is_err());
+    }
+
+    #[test]
+    fn detects_the_format() {
+        assert!(is_toml(Path::new("rfs.toml"), ""));
+        assert!(is_toml(Path::new("rfs_config"), "# comment\n\n[[client]]\n"));
+        assert!(!is_toml(Path::new("rfs_config"), "# [[client]]\nclient:cli1:MTIzNDU2\n"));
+    }
+
+    #[test]
+    fn formats_errors_readably() {
+        let (_, errors) = parse("rfs_config", "client:cli1:MTIzNDU2\nclient:cli1:MTIzNDU2\n");
+        assert_eq!(errors[0].to_string(), "rfs_config:2: duplicate name `cli1`");
+        let (_, errors) = parse("rfs_config", "server:srv1:enl4d3Z1:localhost:0\n");
+        assert_eq!(errors[0].to_string(), "rfs_config:1: invalid port `0`, instead of 1 to 65535");
+        let error = RfsConfig::new().get_from_name(String::from("cli1")).err().unwrap();
+        assert_eq!(error.get_problem(), RfsConfigProblem::NoSuchName);
+        assert_eq!(error.get_file(), None);
+        assert_eq!(error.to_string(), "no client or server named `cli1`");
+    }
+
+    #[test]
+    fn checks_ports() {
+        assert_eq!(parse_port("4242", "f", 1).unwrap(), "4242");
+        assert_eq!(parse_port("65535", "f", 1).unwrap(), "65535");
+        for port in &["0", "65536", "-1", "", "4242\nevil = 1"] {
+            assert_eq!(parse_port(port, "f", 7).unwrap_err().get_line(), Some(7), "{:?}", port);
+        }
+    }
+
+    #[test]
+    fn read_only_permissions_allow_reads() {
+        let permissions = Permissions::new(Access::ReadOnly, Vec::new(), None);
+        for message_type in &[MessageType::ReadFile, MessageType::Stat, MessageType::ListDir,
+                              MessageType::ReadHandle, MessageType::StatHandle] {
+            assert!(permissions.allows(*message_type), "{:?}", message_type);
+        }
+        for message_type in &[MessageType::WriteFile, MessageType::Mkdir, MessageType::Rename,
+                              MessageType::WriteHandle, MessageType::TruncateHandle] {
+            assert!(!permissions.allows(*message_type), "{:?}", message_type);
+        }
+        assert!(permissions.allows_open(true, false, false));
+        assert!(!permissions.allows_open(true, true, false));
+    }
+
+    #[test]
+    fn operations_restrict_permissions() {
+        let operations = Some(vec![MessageType::WriteFile, MessageType::Stat]);
+        let permissions = Permissions::new(Access::ReadWrite, Vec::new(), operations);
+        assert!(permissions.allows(MessageType::WriteFile));
+        assert!(permissions.allows(MessageType::WriteHandle));
+        assert!(permissions.allows(MessageType::StatHandle));
+        assert!(!permissions.allows(MessageType::ReadFile));
+        assert!(!permissions.allows(MessageType::Unlink));
+        assert!(permissions.allows(MessageType::Open));
+        assert!(permissions.allows(MessageType::Close));
+        assert!(permissions.allows_open(false, true, false));
+        assert!(!permissions.allows_open(false, true, true));
+        assert!(!permissions.allows_open(true, true, false));
+
+        let operations = operations_of_names(vec!["write", "read"].into_iter(), "f", 1).unwrap();
+        let read_only = Permissions::new(Access::ReadOnly, Vec::new(), Some(operations));
+        assert!(!read_only.allows(MessageType::WriteFile));
+        assert!(read_only.allows(MessageType::ReadFile));
+        assert!(Permissions::default().allows(MessageType::Rmdir));
+    }
+}
@@ -0,0 +1,616 @@
+//! This module edits configuration files, in the legacy or in the TOML format. Entries are added,
+//! removed or updated in place: the other entries, comments and options of the file are kept.
+
+use crate::config::{RfsConfig, RfsConfigError, Field, Access, Permissions, is_toml,
+                    name_of_operation, parse_port};
+use crate::rfs_common::{Named, BlowfishKey};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::io::Result as IoResult;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+
+/// A configuration file being edited. Changes are written by `ConfigFile::save`.
+pub struct ConfigFile {
+    path: PathBuf,
+    content: String,
+    toml: bool,
+}
+
+/// An entry of a configuration file: the bytes of the whole entry, and those of its key.
+struct Entry {
+    name: String,
+    entry: Range<usize>,
+    key: Range<usize>,
+}
+
+#[derive(Deserialize)]
+struct TomlEntries {
+    #[serde(default)]
+    server: Vec<TomlEntry>,
+    #[serde(default)]
+    client: Vec<TomlEntry>,
+}
+
+#[derive(Deserialize)]
+struct TomlEntry {
+    name: Spanned<String>,
+    key: Spanned<String>,
+}
+
+impl ConfigFile {
+    /// Open the configuration file `path`. If it does not exist, it is created by
+    /// `ConfigFile::save`, in TOML format if its extension is `toml`, in the legacy format
+    /// otherwise.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ConfigFileError> {
+        let path = path.as_ref();
+        let mut content = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => (),
+            Err(e) => return Err(ConfigFileError::new(ConfigFileErrorKind::Io(e))),
+        }
+        let config_file = ConfigFile {
+            path: path.to_path_buf(),
+            toml: is_toml(path, &content),
+            content: content,
+        };
+        config_file.entries()?;
+        Ok(config_file)
+    }
+
+    /// Add the entry `field` at the end of the file. Returns the text of the entry.
+    pub fn add(&mut self, field: &Field) -> Result<String, ConfigFileError> {
+        if self.find(field.get_name()).is_ok() {
+            return Err(ConfigFileError::new(
+                ConfigFileErrorKind::DuplicateName(field.get_name().clone()),
+            ));
+        }
+        if let Field::Server { ref port, .. } = *field {
+            let separator = if self.toml && !self.content.is_empty() { 1 } else { 0 };
+            let line = self.content.lines().count() + separator + 1;
+            parse_port(port, &self.path.display().to_string(), line).map_err(|e| {
+                ConfigFileError::new(ConfigFileErrorKind::Invalid(e))
+            })?;
+        }
+        let text = if self.toml {
+            toml_entry(field)
+        } else {
+            legacy_entry(field)?
+        };
+        if !self.content.is_empty() && !self.content.ends_with('\n') {
+            self.content.push('\n');
+        }
+        if self.toml && !self.content.is_empty() {
+            self.content.push('\n');
+        }
+        self.content.push_str(&text);
+        Ok(text)
+    }
+
+    /// Remove the entry named `name`. Returns the text of the removed entry.
+    pub fn remove(&mut self, name: &str) -> Result<String, ConfigFileError> {
+        let entry = self.find(name)?;
+        let text = self.content[entry.entry.clone()].to_string();
+        self.content.replace_range(entry.entry, "");
+        Ok(text)
+    }
+
+    /// Replace the key of the entry named `name` by `key`. Returns the text of the updated entry.
+    pub fn rotate(&mut self, name: &str, key: &BlowfishKey) -> Result<String, ConfigFileError> {
+        let entry = self.find(name)?;
+        let encoded = base64::encode(key);
+        let encoded = if self.toml {
+            toml::Value::String(encoded).to_string()
+        } else {
+            encoded
+        };
+        self.content.replace_range(entry.key, &encoded);
+        let entry = self.find(name)?;
+        Ok(self.content[entry.entry].to_string())
+    }
+
+    /// Returns the problems `RfsConfig::load` would find in the file, as currently edited.
+    pub fn problems(&self) -> Vec<RfsConfigError> {
+        RfsConfig::parse_content(&self.path, &self.content).1
+    }
+
+    /// Write the configuration file. The file is replaced at once, so that a server reading it at
+    /// the same time sees either the old or the new configuration.
+    pub fn save(&self) -> Result<(), ConfigFileError> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        create_temporary(Path::new(&temporary), &self.path)
+            .and_then(|mut f| f.write_all(self.content.as_bytes()).and_then(|_| f.sync_all()))
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .map_err(|e| ConfigFileError::new(ConfigFileErrorKind::Io(e)))
+    }
+
+    fn find(&self, name: &str) -> Result<Entry, ConfigFileError> {
+        self.entries()?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(ConfigFileError::new(
+                ConfigFileErrorKind::NoSuchName(name.to_string()),
+            ))
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>, ConfigFileError> {
+        if self.toml {
+            toml_entries(&self.content)
+        } else {
+            Ok(legacy_entries(&self.content))
+        }
+    }
+}
+
+/// Create the file `temporary` which replaces `original` when saving. It gets the permissions of
+/// `original`, or is only readable and writable by its owner if `original` does not exist, since
+/// configuration files hold keys.
+#[cfg(unix)]
+fn create_temporary(temporary: &Path, original: &Path) -> IoResult<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(temporary)?;
+    let permissions = match fs::metadata(original) {
+        Ok(metadata) => metadata.permissions(),
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => fs::Permissions::from_mode(0o600),
+        Err(e) => return Err(e),
+    };
+    file.set_permissions(permissions)?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_temporary(temporary: &Path, original: &Path) -> IoResult<File> {
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(temporary)?;
+    match fs::metadata(original) {
+        Ok(metadata) => file.set_permissions(metadata.permissions())?,
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    Ok(file)
+}
+
+/// Returns the lines of `content`, including their end of line, along with their offset.
+fn lines_with_offsets(content: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            (start, line)
+        })
+        .collect()
+}
+
+fn legacy_entries(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (start, line) in lines_with_offsets(content) {
+        let elem: Vec<&str> = line.trim_end_matches(['\n', '\r'])
+            .split(':')
+            .collect();
+        if (elem[0] == "client" || elem[0] == "server") && elem.len() >= 3 {
+            let key_start = start + elem[0].len() + elem[1].len() + 2;
+            entries.push(Entry {
+                name: elem[1].to_string(),
+                entry: start..start + line.len(),
+                key: key_start..key_start + elem[2].len(),
+            });
+        }
+    }
+    entries
+}
+
+fn toml_entries(content: &str) -> Result<Vec<Entry>, ConfigFileError> {
+    let toml_entries: TomlEntries = toml::from_str(content).map_err(|e| {
+        ConfigFileError::new(ConfigFileErrorKind::Syntax(e.to_string()))
+    })?;
+    let lines = lines_with_offsets(content);
+    let is_header = |line: &str| line.trim_start().starts_with('[');
+    Ok(
+        toml_entries
+            .server
+            .into_iter()
+            .chain(toml_entries.client)
+            .map(|entry| {
+                let name = entry.name.start();
+                let start = lines
+                    .iter()
+                    .rev()
+                    .find(|&&(start, line)| start <= name && is_header(line))
+                    .map_or(0, |&(start, _)| start);
+                let end = lines
+                    .iter()
+                    .find(|&&(start, line)| start > name && is_header(line))
+                    .map_or(content.len(), |&(start, _)| start);
+                Entry {
+                    name: entry.name.get_ref().clone(),
+                    entry: start..end,
+                    key: entry.key.start()..entry.key.end(),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Returns the line describing `field` in the legacy format.
+fn legacy_entry(field: &Field) -> Result<String, ConfigFileError> {
+    let mut elem = match *field {
+        Field::Client {
+            ref name,
+            ref key,
+            ref root,
+            ref permissions,
+        } => {
+            let mut elem = vec![
+                "client".to_string(),
+                name.clone(),
+                base64::encode(key),
+                root.clone().unwrap_or_default(),
+            ];
+            elem.extend(permission_names(permissions).into_iter().map(|names| names.join(",")));
+            elem
+        }
+        Field::Server {
+            ref name,
+            ref key,
+            ref address,
+            ref port,
+            ref tls,
+            ref root,
+        } => {
+            vec![
+                "server".to_string(),
+                name.clone(),
+                base64::encode(key),
+                address.clone(),
+                port.clone(),
+                tls.as_ref().map_or(String::new(), |t| t.get_certificates().clone()),
+                tls.as_ref().map_or(String::new(), |t| t.get_private_key().clone()),
+                root.clone().unwrap_or_default(),
+            ]
+        }
+    };
+    if let Some(invalid) = elem.iter().find(|e| e.contains(':') || e.contains('\n')) {
+        return Err(ConfigFileError::new(
+            ConfigFileErrorKind::InvalidField(invalid.clone()),
+        ));
+    }
+    while elem.last().is_some_and(|e| e.is_empty()) {
+        elem.pop();
+    }
+    Ok(elem.join(":") + "\n")
+}
+
+/// Returns the table describing `field` in TOML format.
+fn toml_entry(field: &Field) -> String {
+    let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let mut text = String::new();
+    match *field {
+        Field::Client {
+            ref name,
+            ref key,
+            ref root,
+            ref permissions,
+        } => {
+            text += "[[client]]\n";
+            text += &format!("name = {}\n", quote(name));
+            text += &format!("key = {}\n", quote(&base64::encode(key)));
+            if let Some(ref root) = *root {
+                text += &format!("root = {}\n", quote(root));
+            }
+            let names = permission_names(permissions);
+            if permissions.get_access() == Access::ReadOnly {
+                text += "access = \"ro\"\n";
+            }
+            let array = |names: &[String]| {
+                toml::Value::Array(names.iter().map(|n| toml::Value::String(n.clone())).collect())
+                    .to_string()
+            };
+            if !names[1].is_empty() {
+                text += &format!("prefixes = {}\n", array(&names[1]));
+            }
+            if permissions.get_operations().is_some() {
+                text += &format!("operations = {}\n", array(&names[2]));
+            }
+        }
+        Field::Server {
+            ref name,
+            ref key,
+            ref address,
+            ref port,
+            ref tls,
+            ref root,
+        } => {
+            text += "[[server]]\n";
+            text += &format!("name = {}\n", quote(name));
+            text += &format!("key = {}\n", quote(&base64::encode(key)));
+            text += &format!("address = {}\n", quote(address));
+            text += &format!("port = {}\n", port);
+            if let Some(ref tls) = *tls {
+                text += &format!("certificates = {}\n", quote(tls.get_certificates()));
+                text += &format!("private_key = {}\n", quote(tls.get_private_key()));
+            }
+            if let Some(ref root) = *root {
+                text += &format!("root = {}\n", quote(root));
+            }
+        }
+    }
+    text
+}
+
+/// Returns the access, prefixes and operations of `permissions`, as named in a config file. The
+/// access of read-write permissions, and the operations of permissions allowing any request, are
+/// empty.
+fn permission_names(permissions: &Permissions) -> Vec<Vec<String>> {
+    let access = match permissions.get_access() {
+        Access::ReadOnly => vec!["ro".to_string()],
+        Access::ReadWrite => Vec::new(),
+    };
+    let operations = permissions.get_operations().map_or(Vec::new(), |operations| {
+        operations
+            .iter()
+            .filter_map(|o| name_of_operation(*o))
+            .map(String::from)
+            .collect()
+    });
+    vec![access, permissions.get_prefixes().clone(), operations]
+}
+
+#[derive(Debug)]
+enum ConfigFileErrorKind {
+    Io(IoError),
+    Syntax(String),
+    NoSuchName(String),
+    DuplicateName(String),
+    InvalidField(String),
+    Invalid(RfsConfigError),
+}
+
+/// The error type for the edition of configuration files.
+#[derive(Debug)]
+pub struct ConfigFileError {
+    kind: ConfigFileErrorKind,
+}
+
+impl ConfigFileError {
+    fn new(kind: ConfigFileErrorKind) -> Self {
+        ConfigFileError { kind: kind }
+    }
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ConfigFileErrorKind::Io(ref e) => write!(f, "ConfigFile Error: {}", e),
+            ConfigFileErrorKind::Syntax(ref reason) => {
+                write!(f, "ConfigFile Error: the file is not well formed: {}", reason)
+            }
+            ConfigFileErrorKind::NoSuchName(ref name) => {
+                write!(f, "ConfigFile Error: no entry named `{}`", name)
+            }
+            ConfigFileErrorKind::DuplicateName(ref name) => {
+                write!(f, "ConfigFile Error: there is already an entry named `{}`", name)
+            }
+            ConfigFileErrorKind::InvalidField(ref field) => {
+                write!(f, "ConfigFile Error: `{}` can not be written in the legacy format", field)
+            }
+            ConfigFileErrorKind::Invalid(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ConfigFileError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ConfigFileErrorKind::Io(_) => "The config file can not be read or written",
+            ConfigFileErrorKind::Syntax(_) => "The config file is not well formed",
+            ConfigFileErrorKind::NoSuchName(_) => "There is no entry with such name in the file",
+            ConfigFileErrorKind::DuplicateName(_) => "There is already an entry with such name",
+            ConfigFileErrorKind::InvalidField(_) => {
+                "A field can not be written in the legacy format (it contains ':')"
+            }
+            ConfigFileErrorKind::Invalid(_) => "The entry is not valid",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::message::MessageType;
+    use crate::rfs_common::Identity;
+
+    const LEGACY: &str = "# clients\n\
+                          client:cli1:MTIzNDU2\n\
+                          server:srv1:enl4d3Z1:localhost:4242\n";
+    const TOML: &str = "# servers\n\
+                        [[server]]\n\
+                        name = \"srv1\"\n\
+                        key = \"enl4d3Z1\"\n\
+                        address = \"localhost\"\n\
+                        port = 4242\n\
+                        \n\
+                        [[client]]\n\
+                        name = \"cli1\"\n\
+                        key = \"MTIzNDU2\"\n";
+
+    fn config_file(name: &str, content: &str) -> ConfigFile {
+        ConfigFile {
+            path: PathBuf::from(name),
+            toml: is_toml(Path::new(name), content),
+            content: content.to_string(),
+        }
+    }
+
+    fn client(name: &str, permissions: Permissions) -> Field {
+        Field::Client {
+            name: name.to_string(),
+            key: b"abcdef".to_vec(),
+            root: Some(format!("{}dir", name)),
+            permissions: permissions,
+        }
+    }
+
+    fn server(name: &str, port: &str) -> Field {
+        Field::Server {
+            name: name.to_string(),
+            key: b"zyxwvu".to_vec(),
+            address: String::from("localhost"),
+            port: port.to_string(),
+            tls: None,
+            root: None,
+        }
+    }
+
+    /// Returns the configuration read from the file as edited, which must be valid.
+    fn parsed(file: &ConfigFile) -> RfsConfig {
+        let (conf, errors) = RfsConfig::parse_content(&file.path, &file.content);
+        assert!(errors.is_empty(), "{}", file.content);
+        conf
+    }
+
+    #[test]
+    fn adds_legacy_entries() {
+        let mut file = config_file("rfs_config", LEGACY);
+        let permissions = Permissions::new(
+            Access::ReadOnly,
+            vec![String::from("docs")],
+            Some(vec![MessageType::ReadFile, MessageType::ListDir]),
+        );
+        let text = file.add(&client("cli2", permissions.clone())).unwrap();
+        assert_eq!(text, "client:cli2:YWJjZGVm:cli2dir:ro:docs:read,list\n");
+        file.add(&server("srv2", "4243")).unwrap();
+        assert!(file.content.starts_with(LEGACY));
+        let conf = parsed(&file);
+        let added = conf.get_from_name(String::from("cli2")).unwrap();
+        assert!(*added == client("cli2", permissions));
+        assert!(*conf.get_from_name(String::from("srv2")).unwrap() == server("srv2", "4243"));
+    }
+
+    #[test]
+    fn adds_toml_entries() {
+        let mut file = config_file("rfs.toml", TOML);
+        let permissions = Permissions::new(Access::ReadOnly, Vec::new(), Some(Vec::new()));
+        file.add(&client("cli2", permissions.clone())).unwrap();
+        file.add(&server("srv2", "4243")).unwrap();
+        assert!(file.content.starts_with(TOML));
+        let conf = parsed(&file);
+        let added = conf.get_from_name(String::from("cli2")).unwrap();
+        assert!(*added == client("cli2", permissions));
+        assert!(*conf.get_from_name(String::from("srv2")).unwrap() == server("srv2", "4243"));
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let mut file = config_file("rfs_config", LEGACY);
+        let error = file.add(&client("cli1", Permissions::default())).unwrap_err();
+        assert_eq!(error.to_string(), "ConfigFile Error: there is already an entry named `cli1`");
+        let colon = Field::Client {
+            name: String::from("cli2"),
+            key: b"abcdef".to_vec(),
+            root: Some(String::from("c:")),
+            permissions: Permissions::default(),
+        };
+        let error = file.add(&colon).unwrap_err();
+        let expected = "ConfigFile Error: `c:` can not be written in the legacy format";
+        assert_eq!(error.to_string(), expected);
+        for port in &["0", "99999", "4242\nevil = 1"] {
+            let error = file.add(&server("srv2", port)).unwrap_err();
+            let expected = format!("rfs_config:4: invalid port `{}`, instead of 1 to 65535", port);
+            assert_eq!(error.to_string(), expected);
+        }
+        assert_eq!(file.content, LEGACY);
+
+        let mut file = config_file("rfs.toml", TOML);
+        let error = file.add(&server("srv2", "0")).unwrap_err();
+        assert_eq!(error.to_string(), "rfs.toml:12: invalid port `0`, instead of 1 to 65535");
+        assert_eq!(file.content, TOML);
+    }
+
+    #[test]
+    fn removes_entries() {
+        let mut file = config_file("rfs_config", LEGACY);
+        assert_eq!(file.remove("cli1").unwrap(), "client:cli1:MTIzNDU2\n");
+        assert_eq!(file.content, "# clients\nserver:srv1:enl4d3Z1:localhost:4242\n");
+        assert!(file.remove("cli1").is_err());
+
+        let mut file = config_file("rfs.toml", TOML);
+        assert!(file.remove("srv1").unwrap().starts_with("[[server]]\nname = \"srv1\""));
+        assert_eq!(file.content, "# servers\n[[client]]\nname = \"cli1\"\nkey = \"MTIzNDU2\"\n");
+        let conf = parsed(&file);
+        assert!(conf.get_from_name(String::from("srv1")).is_err());
+        assert!(conf.get_from_name(String::from("cli1")).is_ok());
+    }
+
+    #[test]
+    fn rotates_keys() {
+        for &(name, content) in &[("rfs_config", LEGACY), ("rfs.toml", TOML)] {
+            let mut file = config_file(name, content);
+            file.rotate("cli1", &b"new key".to_vec()).unwrap();
+            file.rotate("srv1", &b"server key".to_vec()).unwrap();
+            let conf = parsed(&file);
+            let cli1 = conf.get_from_name(String::from("cli1")).unwrap();
+            assert_eq!(cli1.get_secret(), b"new key");
+            let srv1 = conf.get_from_name(String::from("srv1")).unwrap();
+            assert_eq!(srv1.get_secret(), b"server key");
+            assert!(file.rotate("cli2", &b"new key".to_vec()).is_err());
+        }
+    }
+
+    #[test]
+    fn reports_problems_of_edited_files() {
+        let mut file = config_file("rfs_config", LEGACY);
+        assert!(file.problems().is_empty());
+        file.content.push_str("client:cli2:MTI=\n");
+        let problems = file.problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].get_line(), Some(4));
+    }
+
+    #[test]
+    fn saves_and_opens_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rfs.toml");
+        let mut file = ConfigFile::open(&path).unwrap();
+        assert!(file.toml);
+        file.add(&client("cli1", Permissions::default())).unwrap();
+        file.save().unwrap();
+        let file = ConfigFile::open(&path).unwrap();
+        assert!(file.content.starts_with("[[client]]\nname = \"cli1\"\n"));
+        let conf = RfsConfig::load(&path).unwrap();
+        let saved = conf.get_from_name(String::from("cli1")).unwrap();
+        assert!(*saved == client("cli1", Permissions::default()));
+        assert!(!dir.path().join("rfs.toml.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_mode_of_saved_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rfs_config");
+        let mut file = ConfigFile::open(&path).unwrap();
+        file.add(&client("cli1", Permissions::default())).unwrap();
+        file.save().unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let mut file = ConfigFile::open(&path).unwrap();
+        file.add(&client("cli2", Permissions::default())).unwrap();
+        file.save().unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert!(RfsConfig::load(&path).unwrap().get_from_name(String::from("cli2")).is_ok());
+    }
+}
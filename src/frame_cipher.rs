@@ -0,0 +1,205 @@
+#![deny(missing_docs)]
+
+//! This module encrypts the frames exchanged after the handshake, so that requests and file
+//! contents do not travel in clear.
+//!
+//! Frames are encrypted with ChaCha20-Poly1305. Each direction of a session has its own key,
+//! derived with HMAC-SHA256 from the secret of the client, the challenges exchanged during the
+//! handshake and the line accepting the protocol, so that keys are fresh for each session and
+//! differ if the negotiation was altered. Nonces are the number of the frame in its
+//! direction: a frame which is dropped, replayed or reordered can not be decrypted.
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use crate::rfs_common::{BlowfishKey, Challenge, SessionId};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Length of the authentication tag appended to each encrypted payload.
+pub const TAG_LENGTH: usize = 16;
+
+const CLIENT_TO_SERVER: &[u8] = b"rfs client to server";
+const SERVER_TO_CLIENT: &[u8] = b"rfs server to client";
+
+/// Encrypts or decrypts the payloads of the frames sent in one direction of a session.
+pub struct FrameCipher {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl FrameCipher {
+    /// Create the cipher of the frames sent by the client, for the session `session_id` in which
+    /// the client sent `client_challenge` and the server accepted the protocol with
+    /// `protocol_line`.
+    pub fn client_to_server(
+        secret: &BlowfishKey,
+        session_id: &SessionId,
+        client_challenge: &Challenge,
+        protocol_line: &str,
+    ) -> Self {
+        FrameCipher::derive(CLIENT_TO_SERVER, secret, session_id, client_challenge, protocol_line)
+    }
+
+    /// Create the cipher of the frames sent by the server, for the session `session_id` in which
+    /// the client sent `client_challenge` and the server accepted the protocol with
+    /// `protocol_line`.
+    pub fn server_to_client(
+        secret: &BlowfishKey,
+        session_id: &SessionId,
+        client_challenge: &Challenge,
+        protocol_line: &str,
+    ) -> Self {
+        FrameCipher::derive(SERVER_TO_CLIENT, secret, session_id, client_challenge, protocol_line)
+    }
+
+    fn derive(
+        direction: &[u8],
+        secret: &BlowfishKey,
+        session_id: &SessionId,
+        client_challenge: &Challenge,
+        protocol_line: &str,
+    ) -> Self {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect(
+            "HMAC accepts keys of any length",
+        );
+        mac.update(direction);
+        mac.update(session_id);
+        mac.update(client_challenge);
+        mac.update(protocol_line.as_bytes());
+        let key = mac.finalize().into_bytes();
+        FrameCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            counter: 0,
+        }
+    }
+
+    /// Returns the nonce of the next frame.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    /// Encrypt `payload`, authenticating `header` along with it. Returns `None` on failure.
+    pub fn seal(&mut self, header: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: header,
+                },
+            )
+            .ok()
+    }
+
+    /// Decrypt `payload`, which must have been sealed with `header`. Returns `None` if the payload
+    /// or the header have been tampered with, or if the frame is not the next one expected.
+    pub fn open(&mut self, header: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: header,
+                },
+            )
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = &[1, 2];
+    const PROTOCOL_LINE: &str = "Protocol accepted: 1; features: encryption";
+
+    /// Returns the cipher of the frames sent in `direction` during the session `session_id`.
+    fn cipher(direction: &[u8], session_id: u8) -> FrameCipher {
+        cipher_with(direction, session_id, PROTOCOL_LINE)
+    }
+
+    fn cipher_with(direction: &[u8], session_id: u8, protocol_line: &str) -> FrameCipher {
+        let (secret, session_id) = (b"secret key".to_vec(), vec![session_id; 8]);
+        FrameCipher::derive(direction, &secret, &session_id, &vec![2; 8], protocol_line)
+    }
+
+    #[test]
+    fn opens_sealed_frames_in_order() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let mut server = cipher(CLIENT_TO_SERVER, 1);
+        for payload in &[&b"first"[..], b"", b"third"] {
+            let sealed = client.seal(HEADER, payload).unwrap();
+            assert_eq!(sealed.len(), payload.len() + TAG_LENGTH);
+            assert_eq!(server.open(HEADER, &sealed).unwrap(), *payload);
+        }
+    }
+
+    #[test]
+    fn uses_a_nonce_per_frame() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let first = client.seal(HEADER, b"payload").unwrap();
+        let second = client.seal(HEADER, b"payload").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(cipher(CLIENT_TO_SERVER, 1).seal(HEADER, b"payload").unwrap(), first);
+    }
+
+    #[test]
+    fn rejects_dropped_frames() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let mut server = cipher(CLIENT_TO_SERVER, 1);
+        client.seal(HEADER, b"first").unwrap();
+        let second = client.seal(HEADER, b"second").unwrap();
+        assert!(server.open(HEADER, &second).is_none());
+    }
+
+    #[test]
+    fn rejects_replayed_frames() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let mut server = cipher(CLIENT_TO_SERVER, 1);
+        let first = client.seal(HEADER, b"first").unwrap();
+        assert!(server.open(HEADER, &first).is_some());
+        assert!(server.open(HEADER, &first).is_none());
+    }
+
+    #[test]
+    fn separates_directions() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let mut server = cipher(SERVER_TO_CLIENT, 1);
+        let request = client.seal(HEADER, b"request").unwrap();
+        assert!(server.open(HEADER, &request).is_none());
+        assert_ne!(server.seal(HEADER, b"request").unwrap(), request);
+    }
+
+    #[test]
+    fn separates_sessions() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let mut server = cipher(CLIENT_TO_SERVER, 3);
+        let sealed = client.seal(HEADER, b"request").unwrap();
+        assert!(server.open(HEADER, &sealed).is_none());
+    }
+
+    #[test]
+    fn separates_protocols() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let protocol_line = "Protocol accepted: 1; features: handles,encryption";
+        let mut server = cipher_with(CLIENT_TO_SERVER, 1, protocol_line);
+        let sealed = client.seal(HEADER, b"request").unwrap();
+        assert!(server.open(HEADER, &sealed).is_none());
+    }
+
+    #[test]
+    fn authenticates_the_header_and_the_payload() {
+        let mut client = cipher(CLIENT_TO_SERVER, 1);
+        let sealed = client.seal(HEADER, b"request").unwrap();
+        assert!(cipher(CLIENT_TO_SERVER, 1).open(&[1, 3], &sealed).is_none());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(cipher(CLIENT_TO_SERVER, 1).open(HEADER, &tampered).is_none());
+        assert!(cipher(CLIENT_TO_SERVER, 1).open(HEADER, &sealed).is_some());
+    }
+}
@@ -0,0 +1,183 @@
+//! This module maps the inodes given to the kernel by `rfs_fuse::RfsFilesystem` to the paths of
+//! the remote files. It does not depend on FUSE, so that it can be tested without libfuse.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+
+/// Inode of the directory exported by the server.
+pub(crate) const ROOT_INODE: u64 = 1;
+
+/// Paths of the files known to the kernel, relative to the exported directory, by inode. Inodes
+/// are allocated on the first lookup of each path, and never reused. An inode is dropped when
+/// the kernel forgets all its lookups, or when its file is removed or replaced.
+pub(crate) struct InodeTable {
+    paths: HashMap<u64, String>,
+    inodes: HashMap<String, u64>,
+    lookups: HashMap<u64, u64>,
+    next: u64,
+}
+
+impl InodeTable {
+    pub(crate) fn new() -> Self {
+        let mut table = InodeTable {
+            paths: HashMap::new(),
+            inodes: HashMap::new(),
+            lookups: HashMap::new(),
+            next: ROOT_INODE + 1,
+        };
+        table.paths.insert(ROOT_INODE, String::new());
+        table.inodes.insert(String::new(), ROOT_INODE);
+        table
+    }
+
+    pub(crate) fn get_path(&self, inode: u64) -> Option<&String> {
+        self.paths.get(&inode)
+    }
+
+    /// Returns the path of `name` in the directory `parent`.
+    pub(crate) fn child_path(&self, parent: u64, name: &OsStr) -> Option<String> {
+        let parent = self.paths.get(&parent)?;
+        let name = name.to_str()?;
+        if parent.is_empty() {
+            Some(name.to_string())
+        } else {
+            Some(format!("{}/{}", parent, name))
+        }
+    }
+
+    /// Returns the inode of `path`, allocating one if needed.
+    pub(crate) fn inode_of(&mut self, path: &str) -> u64 {
+        if let Some(inode) = self.inodes.get(path) {
+            return *inode;
+        }
+        let inode = self.next;
+        self.next += 1;
+        self.paths.insert(inode, path.to_string());
+        self.inodes.insert(path.to_string(), inode);
+        inode
+    }
+
+    /// Returns the inode of `path`, allocating one if needed, and counts the lookup the kernel
+    /// makes by receiving it.
+    pub(crate) fn lookup(&mut self, path: &str) -> u64 {
+        let inode = self.inode_of(path);
+        *self.lookups.entry(inode).or_insert(0) += 1;
+        inode
+    }
+
+    /// Forget `count` lookups of `inode`. The inode is dropped once the kernel has forgotten all
+    /// of them, except the one of the exported directory.
+    pub(crate) fn forget(&mut self, inode: u64, count: u64) {
+        let remaining = match self.lookups.get_mut(&inode) {
+            Some(lookups) => {
+                *lookups = lookups.saturating_sub(count);
+                *lookups
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.lookups.remove(&inode);
+            if inode != ROOT_INODE {
+                if let Some(path) = self.paths.remove(&inode) {
+                    self.inodes.remove(&path);
+                }
+            }
+        }
+    }
+
+    /// Move `from`, and everything below it, to `to`. The paths replaced by the move, i.e. `to`
+    /// and everything below it, are forgotten.
+    pub(crate) fn rename(&mut self, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        let to_prefix = format!("{}/", to);
+        let replaced: Vec<String> = self.inodes
+            .keys()
+            .filter(|path| *path == to || path.starts_with(&to_prefix))
+            .cloned()
+            .collect();
+        for path in replaced {
+            self.remove(&path);
+        }
+        let prefix = format!("{}/", from);
+        let moved: Vec<(String, u64)> = self.inodes
+            .iter()
+            .filter(|&(path, _)| path == from || path.starts_with(&prefix))
+            .map(|(path, inode)| (path.clone(), *inode))
+            .collect();
+        for (path, inode) in moved {
+            let new_path = format!("{}{}", to, &path[from.len()..]);
+            self.inodes.remove(&path);
+            self.inodes.insert(new_path.clone(), inode);
+            self.paths.insert(inode, new_path);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, path: &str) {
+        if let Some(inode) = self.inodes.remove(path) {
+            self.paths.remove(&inode);
+            self.lookups.remove(&inode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(table: &InodeTable, inode: u64) -> Option<&str> {
+        table.get_path(inode).map(String::as_str)
+    }
+
+    #[test]
+    fn allocates_inodes_by_path() {
+        let mut table = InodeTable::new();
+        assert_eq!(path(&table, ROOT_INODE), Some(""));
+        let dir = table.lookup("dir");
+        assert_eq!(table.child_path(dir, OsStr::new("file")), Some(String::from("dir/file")));
+        assert_eq!(table.child_path(ROOT_INODE, OsStr::new("dir")), Some(String::from("dir")));
+        let file = table.lookup("dir/file");
+        assert_ne!(file, dir);
+        assert_eq!(table.inode_of("dir/file"), file);
+        assert_eq!(table.child_path(file + 1, OsStr::new("file")), None);
+    }
+
+    #[test]
+    fn renames_paths_below_the_moved_one() {
+        let mut table = InodeTable::new();
+        let dir = table.lookup("dir");
+        let file = table.lookup("dir/file");
+        let other = table.lookup("directory");
+        let replaced = table.lookup("moved");
+        let replaced_child = table.lookup("moved/file");
+        table.rename("dir", "moved");
+        assert_eq!(path(&table, dir), Some("moved"));
+        assert_eq!(path(&table, file), Some("moved/file"));
+        assert_eq!(path(&table, other), Some("directory"));
+        assert_eq!(path(&table, replaced), None);
+        assert_eq!(path(&table, replaced_child), None);
+        assert_eq!(table.inode_of("moved/file"), file);
+        assert_eq!(table.inode_of("dir"), replaced_child + 1);
+
+        table.rename("moved", "moved");
+        assert_eq!(path(&table, dir), Some("moved"));
+    }
+
+    #[test]
+    fn forgets_inodes_without_lookups() {
+        let mut table = InodeTable::new();
+        let file = table.lookup("file");
+        assert_eq!(table.lookup("file"), file);
+        table.forget(file, 1);
+        assert_eq!(path(&table, file), Some("file"));
+        table.forget(file, 1);
+        assert_eq!(path(&table, file), None);
+        assert_ne!(table.lookup("file"), file);
+
+        table.lookup("");
+        table.forget(ROOT_INODE, 1);
+        assert_eq!(path(&table, ROOT_INODE), Some(""));
+        table.forget(file, 1);
+    }
+}
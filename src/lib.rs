@@ -1,18 +1,31 @@
+// Fields are initialized as `field: field` throughout the crate.
+#![allow(clippy::redundant_field_names)]
+
 #[macro_use]
 extern crate log;
-extern crate env_logger;
-extern crate base64;
-extern crate generic_array;
-extern crate blowfish;
-extern crate block_cipher_trait;
-extern crate bincode;
 #[macro_use]
 extern crate serde_derive;
-extern crate rand;
 
 pub mod message;
 pub mod message_signer;
+pub mod frame_cipher;
 pub mod rfs_common;
+pub mod protocol;
 pub mod rfs_client;
+pub mod rfs_file;
 pub mod rfs_server;
 pub mod config;
+pub mod config_file;
+pub mod live_config;
+pub mod sandbox;
+pub mod cli;
+#[cfg(feature = "async")]
+pub mod rfs_async_server;
+#[cfg(feature = "async")]
+pub mod rfs_async_client;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "fuse")]
+pub mod rfs_fuse;
+#[cfg(any(feature = "fuse", test))]
+mod inode_table;
@@ -0,0 +1,187 @@
+//! This module lets a running server replace its configuration, e.g. to add or revoke clients,
+//! without a restart. The configuration is reloaded from its file on demand, when the file is
+//! modified, or (on Unix) when the server receives `SIGHUP`.
+
+use crate::config::{RfsConfig, RfsConfigError, Config, Field};
+use crate::rfs_common::Named;
+use std::io::Result as IoResult;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+/// What happens to the sessions opened before a reload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionPolicy {
+    /// Sessions continue with the configuration they were opened with.
+    Continue,
+    /// Sessions of clients which have been removed from the configuration, or whose entry (key,
+    /// root or permissions) has changed, are terminated at their next request. The other
+    /// sessions continue.
+    TerminateRevoked,
+}
+
+/// The configuration of a running server. Handshakes use the configuration current when they
+/// start; a reload swaps the whole configuration at once, so that a handshake never sees a
+/// partially updated configuration.
+#[derive(Clone)]
+pub struct LiveConfig {
+    current: Arc<RwLock<Arc<RfsConfig>>>,
+    policy: Arc<RwLock<SessionPolicy>>,
+}
+
+impl LiveConfig {
+    /// Create a live configuration, initially `config`. Sessions continue after a reload.
+    pub fn new(config: RfsConfig) -> Self {
+        LiveConfig {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+            policy: Arc::new(RwLock::new(SessionPolicy::Continue)),
+        }
+    }
+
+    /// Returns the current configuration.
+    pub fn get(&self) -> Arc<RfsConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Replace the configuration by `config`.
+    pub fn replace(&self, config: RfsConfig) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+
+    pub fn get_policy(&self) -> SessionPolicy {
+        *self.policy.read().unwrap()
+    }
+
+    /// Set what happens to the sessions opened before a reload.
+    pub fn set_policy(&self, policy: SessionPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// Reload the configuration from the file `path`. If the file is not valid, the current
+    /// configuration is kept.
+    pub fn reload<P: AsRef<Path>>(&self, path: P) -> Result<(), RfsConfigError> {
+        let config = RfsConfig::load(path.as_ref())?;
+        self.replace(config);
+        info!("Configuration reloaded from {}", path.as_ref().display());
+        Ok(())
+    }
+
+    /// Reload the configuration whenever the modification time of the file `path` changes. The
+    /// file is checked every `interval`, by a background thread.
+    pub fn watch<P: AsRef<Path>>(&self, path: P, interval: Duration) -> JoinHandle<()> {
+        let live = self.clone();
+        let path: PathBuf = path.as_ref().to_path_buf();
+        thread::spawn(move || {
+            let mut modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            loop {
+                thread::sleep(interval);
+                let now = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                if now.is_some() && now != modified {
+                    modified = now;
+                    live.reload_or_warn(&path);
+                }
+            }
+        })
+    }
+
+    /// Reload the configuration from the file `path` whenever the process receives `SIGHUP`.
+    #[cfg(unix)]
+    pub fn reload_on_sighup<P: AsRef<Path>>(&self, path: P) -> IoResult<JoinHandle<()>> {
+        let mut signals = Signals::new([SIGHUP])?;
+        let live = self.clone();
+        let path: PathBuf = path.as_ref().to_path_buf();
+        Ok(thread::spawn(move || for _ in signals.forever() {
+            info!("SIGHUP received");
+            live.reload_or_warn(&path);
+        }))
+    }
+
+    fn reload_or_warn(&self, path: &Path) {
+        if let Err(e) = self.reload(path) {
+            error!("Configuration not reloaded, keeping the previous one. Reason: {}", e);
+        }
+    }
+
+    /// Returns whether the session of `client`, opened before the last reload, must be
+    /// terminated according to the policy. The session runs with the key, root and permissions
+    /// of `client`, so any change of its entry revokes it.
+    pub(crate) fn is_revoked(&self, client: &Field) -> bool {
+        if self.get_policy() == SessionPolicy::Continue {
+            return false;
+        }
+        match self.get().get_from_name(client.get_name().clone()) {
+            Ok(current @ &Field::Client { .. }) => current != client,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "client:cli1:MTIzNDU2\nclient:cli2:YWJjZGVm\n";
+
+    fn config(content: &str) -> RfsConfig {
+        let (conf, errors) = RfsConfig::parse_content(Path::new("rfs_config"), content);
+        assert!(errors.is_empty());
+        conf
+    }
+
+    /// Returns the entry of the client `name` in `config`.
+    fn client(config: &RfsConfig, name: &str) -> Field {
+        config.get_from_name(String::from(name)).unwrap().clone()
+    }
+
+    #[test]
+    fn reloads_valid_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rfs_config");
+        let live = LiveConfig::new(config(CONFIG));
+        fs::write(&path, "client:cli3:MTIzNDU2\n").unwrap();
+        live.reload(&path).unwrap();
+        assert!(live.get().get_from_name(String::from("cli1")).is_err());
+        assert!(live.get().get_from_name(String::from("cli3")).is_ok());
+
+        fs::write(&path, "client:cli4:MTI=\n").unwrap();
+        assert!(live.reload(&path).is_err());
+        assert!(live.get().get_from_name(String::from("cli3")).is_ok());
+        assert!(live.reload(dir.path().join("missing")).is_err());
+        assert!(live.get().get_from_name(String::from("cli3")).is_ok());
+    }
+
+    #[test]
+    fn sessions_continue_by_default() {
+        let live = LiveConfig::new(config(CONFIG));
+        let cli1 = client(&live.get(), "cli1");
+        assert_eq!(live.get_policy(), SessionPolicy::Continue);
+        live.replace(RfsConfig::new());
+        assert!(!live.is_revoked(&cli1));
+    }
+
+    #[test]
+    fn terminates_sessions_of_removed_or_changed_clients() {
+        let live = LiveConfig::new(config(CONFIG));
+        live.set_policy(SessionPolicy::TerminateRevoked);
+        let cli1 = client(&live.get(), "cli1");
+        let cli2 = client(&live.get(), "cli2");
+        assert!(!live.is_revoked(&cli1));
+
+        live.replace(config("client:cli1:MTIzNDU2::ro\nclient:cli2:YWJjZGVm\n"));
+        assert!(live.is_revoked(&cli1));
+        assert!(!live.is_revoked(&cli2));
+
+        live.replace(config("client:cli1:MTIzNDU2\n"));
+        assert!(!live.is_revoked(&cli1));
+        assert!(live.is_revoked(&cli2));
+
+        live.replace(config("server:cli2:YWJjZGVm:localhost:4242\n"));
+        assert!(live.is_revoked(&cli2));
+    }
+}
@@ -1,4 +1,85 @@
 use bincode::{serialize, deserialize, Bounded};
+use crate::rfs_common::MAX_FRAME_LENGTH;
+use std::fs::{FileType, Metadata, OpenOptions};
+use std::io::{Error as IoError, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Type of a message, so that the receiver of a `message_signer::SignedMessage` knows how to
+/// deserialize it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    WriteFile,
+    WriteFileReply,
+    ReadFile,
+    ReadFileReply,
+    Stat,
+    StatReply,
+    ListDir,
+    ListDirReply,
+    Mkdir,
+    MkdirReply,
+    Rmdir,
+    RmdirReply,
+    Unlink,
+    UnlinkReply,
+    Rename,
+    RenameReply,
+    Truncate,
+    TruncateReply,
+    SetTimes,
+    SetTimesReply,
+    Open,
+    OpenReply,
+    Close,
+    CloseReply,
+    ReadHandle,
+    WriteHandle,
+    StatHandle,
+    TruncateHandle,
+}
+
+impl MessageType {
+    /// Returns the tag identifying the message type in a `rfs_common::Frame`.
+    pub fn tag(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Returns the message type identified by `tag`, if any.
+    pub fn from_tag(tag: u8) -> Option<MessageType> {
+        MESSAGE_TYPES.iter().cloned().find(|t| t.tag() == tag)
+    }
+}
+
+const MESSAGE_TYPES: [MessageType; 28] = [
+    MessageType::WriteFile,
+    MessageType::WriteFileReply,
+    MessageType::ReadFile,
+    MessageType::ReadFileReply,
+    MessageType::Stat,
+    MessageType::StatReply,
+    MessageType::ListDir,
+    MessageType::ListDirReply,
+    MessageType::Mkdir,
+    MessageType::MkdirReply,
+    MessageType::Rmdir,
+    MessageType::RmdirReply,
+    MessageType::Unlink,
+    MessageType::UnlinkReply,
+    MessageType::Rename,
+    MessageType::RenameReply,
+    MessageType::Truncate,
+    MessageType::TruncateReply,
+    MessageType::SetTimes,
+    MessageType::SetTimesReply,
+    MessageType::Open,
+    MessageType::OpenReply,
+    MessageType::Close,
+    MessageType::CloseReply,
+    MessageType::ReadHandle,
+    MessageType::WriteHandle,
+    MessageType::StatHandle,
+    MessageType::TruncateHandle,
+];
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WriteFile {
@@ -7,11 +88,270 @@ pub struct WriteFile {
     filename: Vec<u8>,
 }
 
+/// Reply of the server to a `WriteFile` request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WriteFileReply {
+    status: Status,
+    written: u64,
+}
+
+/// Request to read at most `length` bytes of `filename`, starting at `position`. Servers read at
+/// most `rfs_common::MAX_CHUNK_LENGTH` bytes per request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadFile {
+    filename: Vec<u8>,
+    position: u64,
+    length: u64,
+}
+
+/// Reply of the server to a `ReadFile` request. `eof` is set when the end of the file has been
+/// reached.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadFileReply {
+    status: Status,
+    data: Vec<u8>,
+    eof: bool,
+}
+
+/// Request for the metadata of `path`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Stat {
+    path: Vec<u8>,
+}
+
+/// Reply of the server to a `Stat` request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatReply {
+    status: Status,
+    stat: Option<FileStat>,
+}
+
+/// Request for the entries of the directory `path`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListDir {
+    path: Vec<u8>,
+}
+
+/// Reply of the server to a `ListDir` request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListDirReply {
+    status: Status,
+    entries: Vec<DirEntry>,
+}
+
+/// Request to create the directory `path`. Its parent must exist.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Mkdir {
+    path: Vec<u8>,
+}
+
+/// Request to remove the empty directory `path`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rmdir {
+    path: Vec<u8>,
+}
+
+/// Request to remove the file `path`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Unlink {
+    path: Vec<u8>,
+}
+
+/// Request to rename `from` into `to`, replacing `to` if it exists.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rename {
+    from: Vec<u8>,
+    to: Vec<u8>,
+}
+
+/// Request to truncate (or extend) the file `path` to `length` bytes.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Truncate {
+    path: Vec<u8>,
+    length: u64,
+}
+
+/// Request to set the access and modification times of `path`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetTimes {
+    path: Vec<u8>,
+    accessed: Timestamp,
+    modified: Timestamp,
+}
+
+/// Request to open `filename`, with the same options as `std::fs::OpenOptions`. The server
+/// replies with a handle, which identifies the open file in the following requests of the
+/// session, until it is closed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Open {
+    filename: Vec<u8>,
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+/// Reply of the server to an `Open` request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenReply {
+    status: Status,
+    handle: u64,
+}
+
+/// Request to close the file opened as `handle`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Close {
+    handle: u64,
+}
+
+/// Request to read at most `length` bytes of the file opened as `handle`, starting at `position`.
+/// The server replies with a `ReadFileReply`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadHandle {
+    handle: u64,
+    position: u64,
+    length: u64,
+}
+
+/// Request to write `content` at `position` in the file opened as `handle`. Files opened in
+/// append mode are written at their end, whatever the position. The server replies with a
+/// `WriteFileReply`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WriteHandle {
+    handle: u64,
+    position: u64,
+    content: Vec<u8>,
+}
+
+/// Request for the metadata of the file opened as `handle`. The server replies with a
+/// `StatReply`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatHandle {
+    handle: u64,
+}
+
+/// Request to truncate (or extend) the file opened as `handle` to `length` bytes. The server
+/// replies with a `TruncateReply`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TruncateHandle {
+    handle: u64,
+    length: u64,
+}
+
+/// Kind of a file on the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// A point in time, as a duration since the Unix epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp {
+    seconds: u64,
+    nanoseconds: u32,
+}
+
+/// Metadata of a file on the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileStat {
+    kind: FileKind,
+    size: u64,
+    mode: u32,
+    accessed: Timestamp,
+    modified: Timestamp,
+}
+
+/// An entry of a directory on the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirEntry {
+    name: Vec<u8>,
+    kind: FileKind,
+}
+
+/// Status of a request, as executed by the server. Apart from `Ok` and `BadMessage`, statuses
+/// mirror the usual errno codes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// The request succeeded.
+    Ok,
+    /// The request could not be decoded by the server.
+    BadMessage,
+    /// The server could not perform the request on its filesystem (`EIO`).
+    IoError,
+    /// No such file or directory (`ENOENT`).
+    NotFound,
+    /// Permission denied (`EACCES`).
+    PermissionDenied,
+    /// File exists (`EEXIST`).
+    AlreadyExists,
+    /// Not a directory (`ENOTDIR`).
+    NotADirectory,
+    /// Is a directory (`EISDIR`).
+    IsADirectory,
+    /// Directory not empty (`ENOTEMPTY`).
+    DirectoryNotEmpty,
+    /// Invalid argument (`EINVAL`).
+    InvalidArgument,
+    /// The handle does not identify an open file (`EBADF`).
+    BadHandle,
+    /// The session has too many open files (`EMFILE`).
+    TooManyOpenFiles,
+    /// The request belongs to a feature which was not negotiated (`ENOTSUP`).
+    Unsupported,
+}
+
+impl From<&IoError> for Status {
+    fn from(e: &IoError) -> Self {
+        match e.kind() {
+            ErrorKind::NotFound => Status::NotFound,
+            ErrorKind::PermissionDenied => Status::PermissionDenied,
+            ErrorKind::AlreadyExists => Status::AlreadyExists,
+            ErrorKind::NotADirectory => Status::NotADirectory,
+            ErrorKind::IsADirectory => Status::IsADirectory,
+            ErrorKind::DirectoryNotEmpty => Status::DirectoryNotEmpty,
+            ErrorKind::InvalidInput => Status::InvalidArgument,
+            _ => Status::IoError,
+        }
+    }
+}
+
+impl Status {
+    /// Returns the kind of `std::io::Error` matching the status.
+    pub fn io_error_kind(&self) -> ErrorKind {
+        match *self {
+            Status::NotFound => ErrorKind::NotFound,
+            Status::PermissionDenied => ErrorKind::PermissionDenied,
+            Status::AlreadyExists => ErrorKind::AlreadyExists,
+            Status::NotADirectory => ErrorKind::NotADirectory,
+            Status::IsADirectory => ErrorKind::IsADirectory,
+            Status::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+            Status::InvalidArgument | Status::BadHandle => ErrorKind::InvalidInput,
+            Status::Unsupported => ErrorKind::Unsupported,
+            Status::Ok | Status::BadMessage | Status::IoError | Status::TooManyOpenFiles => {
+                ErrorKind::Other
+            }
+        }
+    }
+}
+
 pub trait Message: Sized {
+    fn message_type() -> MessageType;
     fn serialize(&self) -> Option<Vec<u8>>;
     fn deserialize(slice: &[u8]) -> Option<Self>;
 }
 
+/// A message sent by the server in response to a request. A reply can always be built from a
+/// failure status alone.
+pub trait Reply: Message {
+    fn from_status(status: Status) -> Self;
+    fn get_status(&self) -> Status;
+}
+
 impl WriteFile {
     pub fn new(content: Vec<u8>, position: u64, name: &str) -> Self {
         WriteFile {
@@ -20,27 +360,695 @@ impl WriteFile {
             filename: name.as_bytes().to_vec(),
         }
     }
+
+    pub fn get_content(&self) -> &[u8] {
+        &self.content
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_filename(&self) -> &[u8] {
+        &self.filename
+    }
+}
+
+impl WriteFileReply {
+    pub fn new(status: Status, written: u64) -> Self {
+        WriteFileReply {
+            status: status,
+            written: written,
+        }
+    }
+
+    pub fn get_written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Reply for WriteFileReply {
+    fn from_status(status: Status) -> Self {
+        WriteFileReply::new(status, 0)
+    }
+
+    fn get_status(&self) -> Status {
+        self.status
+    }
+}
+
+impl ReadFile {
+    pub fn new(name: &str, position: u64, length: u64) -> Self {
+        ReadFile {
+            filename: name.as_bytes().to_vec(),
+            position: position,
+            length: length,
+        }
+    }
+
+    pub fn get_filename(&self) -> &[u8] {
+        &self.filename
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl ReadFileReply {
+    pub fn new(status: Status, data: Vec<u8>, eof: bool) -> Self {
+        ReadFileReply {
+            status: status,
+            data: data,
+            eof: eof,
+        }
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the reply, returning the data read.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+}
+
+impl Reply for ReadFileReply {
+    fn from_status(status: Status) -> Self {
+        ReadFileReply::new(status, Vec::new(), false)
+    }
+
+    fn get_status(&self) -> Status {
+        self.status
+    }
+}
+
+impl Stat {
+    pub fn new(path: &str) -> Self {
+        Stat { path: path.as_bytes().to_vec() }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+}
+
+impl StatReply {
+    pub fn new(stat: FileStat) -> Self {
+        StatReply {
+            status: Status::Ok,
+            stat: Some(stat),
+        }
+    }
+
+    /// Consumes the reply, returning the metadata. Only `None` if the status is not `Status::Ok`.
+    pub fn into_stat(self) -> Option<FileStat> {
+        self.stat
+    }
+}
+
+impl Reply for StatReply {
+    fn from_status(status: Status) -> Self {
+        StatReply {
+            status: status,
+            stat: None,
+        }
+    }
+
+    fn get_status(&self) -> Status {
+        self.status
+    }
+}
+
+impl ListDir {
+    pub fn new(path: &str) -> Self {
+        ListDir { path: path.as_bytes().to_vec() }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+}
+
+impl ListDirReply {
+    pub fn new(entries: Vec<DirEntry>) -> Self {
+        ListDirReply {
+            status: Status::Ok,
+            entries: entries,
+        }
+    }
+
+    /// Consumes the reply, returning the entries of the directory.
+    pub fn into_entries(self) -> Vec<DirEntry> {
+        self.entries
+    }
 }
 
-impl Message for WriteFile {
-    fn serialize(&self) -> Option<Vec<u8>> {
-        let limit = Bounded(512);
-        match serialize(&self, limit) {
-            Ok(vec) => Some(vec),
-            Err(e) => {
-                warn!("Could not serialize WriteFile message. Reason: {}", e);
-                None
+impl Reply for ListDirReply {
+    fn from_status(status: Status) -> Self {
+        ListDirReply {
+            status: status,
+            entries: Vec::new(),
+        }
+    }
+
+    fn get_status(&self) -> Status {
+        self.status
+    }
+}
+
+impl Mkdir {
+    pub fn new(path: &str) -> Self {
+        Mkdir { path: path.as_bytes().to_vec() }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+}
+
+impl Rmdir {
+    pub fn new(path: &str) -> Self {
+        Rmdir { path: path.as_bytes().to_vec() }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+}
+
+impl Unlink {
+    pub fn new(path: &str) -> Self {
+        Unlink { path: path.as_bytes().to_vec() }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+}
+
+impl Rename {
+    pub fn new(from: &str, to: &str) -> Self {
+        Rename {
+            from: from.as_bytes().to_vec(),
+            to: to.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn get_from(&self) -> &[u8] {
+        &self.from
+    }
+
+    pub fn get_to(&self) -> &[u8] {
+        &self.to
+    }
+}
+
+impl Truncate {
+    pub fn new(path: &str, length: u64) -> Self {
+        Truncate {
+            path: path.as_bytes().to_vec(),
+            length: length,
+        }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl SetTimes {
+    pub fn new(path: &str, accessed: Timestamp, modified: Timestamp) -> Self {
+        SetTimes {
+            path: path.as_bytes().to_vec(),
+            accessed: accessed,
+            modified: modified,
+        }
+    }
+
+    pub fn get_path(&self) -> &[u8] {
+        &self.path
+    }
+
+    pub fn get_accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    pub fn get_modified(&self) -> Timestamp {
+        self.modified
+    }
+}
+
+impl Open {
+    pub fn new(
+        name: &str,
+        read: bool,
+        write: bool,
+        append: bool,
+        truncate: bool,
+        create: bool,
+        create_new: bool,
+    ) -> Self {
+        Open {
+            filename: name.as_bytes().to_vec(),
+            read: read,
+            write: write,
+            append: append,
+            truncate: truncate,
+            create: create,
+            create_new: create_new,
+        }
+    }
+
+    pub fn get_filename(&self) -> &[u8] {
+        &self.filename
+    }
+
+    pub fn is_read(&self) -> bool {
+        self.read
+    }
+
+    pub fn is_truncate(&self) -> bool {
+        self.truncate
+    }
+
+    /// Returns whether the file is opened for writing, in any way.
+    pub fn is_write(&self) -> bool {
+        self.write || self.append || self.truncate || self.create || self.create_new
+    }
+
+    /// Returns the options of `std::fs` matching the request.
+    pub fn to_open_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options
+            .read(self.read)
+            .write(self.write)
+            .append(self.append)
+            .truncate(self.truncate)
+            .create(self.create)
+            .create_new(self.create_new);
+        options
+    }
+}
+
+impl OpenReply {
+    pub fn new(handle: u64) -> Self {
+        OpenReply {
+            status: Status::Ok,
+            handle: handle,
+        }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+}
+
+impl Reply for OpenReply {
+    fn from_status(status: Status) -> Self {
+        OpenReply {
+            status: status,
+            handle: 0,
+        }
+    }
+
+    fn get_status(&self) -> Status {
+        self.status
+    }
+}
+
+impl Close {
+    pub fn new(handle: u64) -> Self {
+        Close { handle: handle }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+}
+
+impl ReadHandle {
+    pub fn new(handle: u64, position: u64, length: u64) -> Self {
+        ReadHandle {
+            handle: handle,
+            position: position,
+            length: length,
+        }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl WriteHandle {
+    pub fn new(handle: u64, position: u64, content: Vec<u8>) -> Self {
+        WriteHandle {
+            handle: handle,
+            position: position,
+            content: content,
+        }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_content(&self) -> &[u8] {
+        &self.content
+    }
+}
+
+impl StatHandle {
+    pub fn new(handle: u64) -> Self {
+        StatHandle { handle: handle }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+}
+
+impl TruncateHandle {
+    pub fn new(handle: u64, length: u64) -> Self {
+        TruncateHandle {
+            handle: handle,
+            length: length,
+        }
+    }
+
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl From<FileType> for FileKind {
+    fn from(t: FileType) -> Self {
+        if t.is_file() {
+            FileKind::File
+        } else if t.is_dir() {
+            FileKind::Directory
+        } else if t.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        }
+    }
+}
+
+impl Timestamp {
+    pub fn new(seconds: u64, nanoseconds: u32) -> Self {
+        Timestamp {
+            seconds: seconds,
+            nanoseconds: nanoseconds,
+        }
+    }
+
+    pub fn get_seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    pub fn get_nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    /// Returns the time represented by the timestamp, or `None` if the timestamp is invalid or
+    /// cannot be represented on this platform.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        if self.nanoseconds >= 1_000_000_000 {
+            return None;
+        }
+        UNIX_EPOCH.checked_add(Duration::new(self.seconds, self.nanoseconds))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    /// Times before the Unix epoch are clamped to the epoch.
+    fn from(t: SystemTime) -> Self {
+        let d = t.duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+        Timestamp::new(d.as_secs(), d.subsec_nanos())
+    }
+}
+
+impl FileStat {
+    pub fn get_kind(&self) -> FileKind {
+        self.kind
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the Unix permission bits of the file. On other platforms, only the write bits are
+    /// meaningful.
+    pub fn get_mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn get_accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    pub fn get_modified(&self) -> Timestamp {
+        self.modified
+    }
+}
+
+impl From<&Metadata> for FileStat {
+    fn from(m: &Metadata) -> Self {
+        FileStat {
+            kind: FileKind::from(m.file_type()),
+            size: m.len(),
+            mode: mode_of(m),
+            accessed: m.accessed().map(Timestamp::from).unwrap_or(
+                Timestamp::new(0, 0),
+            ),
+            modified: m.modified().map(Timestamp::from).unwrap_or(
+                Timestamp::new(0, 0),
+            ),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(m: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    m.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(m: &Metadata) -> u32 {
+    if m.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+impl DirEntry {
+    pub fn new(name: Vec<u8>, kind: FileKind) -> Self {
+        DirEntry {
+            name: name,
+            kind: kind,
+        }
+    }
+
+    pub fn get_name(&self) -> &[u8] {
+        &self.name
+    }
+
+    pub fn get_kind(&self) -> FileKind {
+        self.kind
+    }
+}
+
+/// Declares a reply which only carries a `Status`.
+macro_rules! status_reply {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Serialize, Deserialize, Debug)]
+        pub struct $name {
+            status: Status,
+        }
+
+        impl Reply for $name {
+            fn from_status(status: Status) -> Self {
+                $name { status: status }
+            }
+
+            fn get_status(&self) -> Status {
+                self.status
             }
         }
     }
+}
+
+status_reply!(
+    /// Reply of the server to a `Mkdir` request.
+    MkdirReply
+);
+status_reply!(
+    /// Reply of the server to a `Rmdir` request.
+    RmdirReply
+);
+status_reply!(
+    /// Reply of the server to a `Unlink` request.
+    UnlinkReply
+);
+status_reply!(
+    /// Reply of the server to a `Rename` request.
+    RenameReply
+);
+status_reply!(
+    /// Reply of the server to a `Truncate` request.
+    TruncateReply
+);
+status_reply!(
+    /// Reply of the server to a `SetTimes` request.
+    SetTimesReply
+);
+status_reply!(
+    /// Reply of the server to a `Close` request.
+    CloseReply
+);
+
+/// Implements `Message` for a serializable structure, using the variant of `MessageType` with the
+/// same name.
+macro_rules! impl_message {
+    ($name:ident) => {
+        impl Message for $name {
+            fn message_type() -> MessageType {
+                MessageType::$name
+            }
 
-    fn deserialize(slice: &[u8]) -> Option<Self> {
-        match deserialize(slice) {
-            Ok(wf) => Some(wf),
-            Err(e) => {
-                warn!("Could not deserialize WriteFile message. Reason: {}", e);
-                None
+            fn serialize(&self) -> Option<Vec<u8>> {
+                let limit = Bounded(MAX_FRAME_LENGTH as u64);
+                match serialize(&self, limit) {
+                    Ok(vec) => Some(vec),
+                    Err(e) => {
+                        warn!("Could not serialize {} message. Reason: {}", stringify!($name), e);
+                        None
+                    }
+                }
+            }
+
+            fn deserialize(slice: &[u8]) -> Option<Self> {
+                match deserialize(slice) {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        warn!("Could not deserialize {} message. Reason: {}", stringify!($name), e);
+                        None
+                    }
+                }
             }
         }
     }
 }
+
+impl_message!(WriteFile);
+impl_message!(WriteFileReply);
+impl_message!(ReadFile);
+impl_message!(ReadFileReply);
+impl_message!(Stat);
+impl_message!(StatReply);
+impl_message!(ListDir);
+impl_message!(ListDirReply);
+impl_message!(Mkdir);
+impl_message!(MkdirReply);
+impl_message!(Rmdir);
+impl_message!(RmdirReply);
+impl_message!(Unlink);
+impl_message!(UnlinkReply);
+impl_message!(Rename);
+impl_message!(RenameReply);
+impl_message!(Truncate);
+impl_message!(TruncateReply);
+impl_message!(SetTimes);
+impl_message!(SetTimesReply);
+impl_message!(Open);
+impl_message!(OpenReply);
+impl_message!(Close);
+impl_message!(CloseReply);
+impl_message!(ReadHandle);
+impl_message!(WriteHandle);
+impl_message!(StatHandle);
+impl_message!(TruncateHandle);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_survive_serialization() {
+        let rename = Rename::deserialize(&Rename::new("a/b", "c").serialize().unwrap()).unwrap();
+        assert_eq!(rename.get_from(), b"a/b");
+        assert_eq!(rename.get_to(), b"c");
+        let times = SetTimes::new("file", Timestamp::new(1, 2), Timestamp::new(3, 4));
+        let times = SetTimes::deserialize(&times.serialize().unwrap()).unwrap();
+        assert_eq!(times.get_path(), b"file");
+        assert_eq!(times.get_accessed(), Timestamp::new(1, 2));
+        assert_eq!(times.get_modified(), Timestamp::new(3, 4));
+    }
+
+    #[test]
+    fn failed_replies_carry_their_status() {
+        let reply = ListDirReply::from_status(Status::NotADirectory);
+        let reply = ListDirReply::deserialize(&reply.serialize().unwrap()).unwrap();
+        assert_eq!(reply.get_status(), Status::NotADirectory);
+        assert!(reply.into_entries().is_empty());
+        assert!(StatReply::from_status(Status::NotFound).into_stat().is_none());
+    }
+
+    #[test]
+    fn maps_io_errors_to_statuses() {
+        let status = |kind| Status::from(&IoError::from(kind));
+        assert_eq!(status(ErrorKind::NotFound), Status::NotFound);
+        assert_eq!(status(ErrorKind::AlreadyExists), Status::AlreadyExists);
+        assert_eq!(status(ErrorKind::InvalidInput), Status::InvalidArgument);
+        assert_eq!(status(ErrorKind::Other), Status::IoError);
+    }
+
+    #[test]
+    fn converts_system_times() {
+        let time = UNIX_EPOCH + Duration::new(1_500_000_000, 42);
+        let timestamp = Timestamp::from(time);
+        assert_eq!(timestamp, Timestamp::new(1_500_000_000, 42));
+        assert_eq!(timestamp.to_system_time(), Some(time));
+        assert_eq!(Timestamp::from(UNIX_EPOCH - Duration::new(1, 0)), Timestamp::new(0, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        assert_eq!(Timestamp::new(0, 1_000_000_000).to_system_time(), None);
+        assert_eq!(Timestamp::new(u64::MAX, 999_999_999).to_system_time(), None);
+    }
+}
@@ -3,36 +3,151 @@
 //! This module contains all the machinery to sign a message, in order to ensure that it indeed
 //! comes from the pretended client (i.e.: to avoid session hijacks).
 //!
-//! Messages (from `message::Message`) are first serialized, then all bytes are XOR-ed into a
-//! checksum. This checksum is then ciphered using the client identity.
+//! Messages (from `message::Message`) are first serialized. The `HmacSigner` (the default) then
+//! computes an HMAC-SHA256 of the message type and serialized message, keyed by the client
+//! identity. The legacy `BlowfishSigner` XORs all bytes into a checksum, which is then ciphered
+//! using the client identity.
+//!
+//! Signatures also cover the identifier of the session and the sequence number of the message, so
+//! that a captured message can not be replayed, neither in the same session nor in another one.
 
-use generic_array::GenericArray;
 use blowfish::Blowfish;
 use std::error::Error;
-use message::Message;
-use rfs_common::BlowfishKey;
-use block_cipher_trait::{BlockCipher, BlockCipherVarKey};
+use crate::message::{Message, MessageType};
+use crate::rfs_common::{encrypt_block, get_cipher, BlowfishKey, Frame, SessionId};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use std::fmt;
 
+/// The `MessageSigner` used by clients and servers.
+pub type DefaultSigner = HmacSigner;
+
 #[derive(Debug)]
 /// A signed message. Typically built by a `MessageSigner`. It contains a serialized `message::Message` and a checksum.
 pub struct SignedMessage {
+    message_type: MessageType,
+    sequence: u64,
     serialized_message: Vec<u8>,
     signature: Vec<u8>,
 }
 
-/// This trait is implemented by structures which perform the signature of the message.
+impl SignedMessage {
+    /// Returns the type of the serialized `message::Message`.
+    pub fn get_message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    /// Returns the sequence number of the message in its session.
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the serialized `message::Message`. It should only be trusted once the signature has
+    /// been checked with `MessageSigner::assert`.
+    pub fn get_message(&self) -> &[u8] {
+        &self.serialized_message
+    }
+
+    /// Builds the `Frame` carrying this message. The payload of the frame is the sequence number
+    /// (8 bytes, big endian), the length of the signature (one byte), the signature, and the
+    /// serialized message.
+    pub fn into_frame(self) -> Frame {
+        let mut payload = Vec::with_capacity(
+            9 + self.signature.len() + self.serialized_message.len(),
+        );
+        payload.extend_from_slice(&self.sequence.to_be_bytes());
+        payload.push(self.signature.len() as u8);
+        payload.extend(self.signature);
+        payload.extend(self.serialized_message);
+        Frame::new(self.message_type, payload)
+    }
+
+    /// Extracts the `SignedMessage` carried by `frame`, or `None` if the frame is malformed.
+    pub fn from_frame(frame: Frame) -> Option<SignedMessage> {
+        let message_type = frame.get_message_type();
+        let mut payload = frame.into_payload();
+        if payload.len() < 9 || payload.len() < 9 + payload[8] as usize {
+            return None;
+        }
+        let serialized_message = payload.split_off(9 + payload[8] as usize);
+        let signature = payload.split_off(9);
+        let mut sequence = [0; 8];
+        sequence.copy_from_slice(&payload[..8]);
+        Some(SignedMessage {
+            message_type: message_type,
+            sequence: u64::from_be_bytes(sequence),
+            serialized_message: serialized_message,
+            signature: signature,
+        })
+    }
+}
+
+/// This trait is implemented by structures which perform the signature of the message. A signer
+/// is bound to a session, and numbers the messages it signs.
 pub trait MessageSigner {
     /// Given a `message::Message`, returns the signed message, or `None` in case of failure.
-    fn sign<M: Message>(&self, message: M) -> Option<SignedMessage>;
+    fn sign<M: Message>(&mut self, message: M) -> Option<SignedMessage>;
     /// Given a `SignedMessage`, assert that the signature is correct with respect to the
-    /// content of the serialized message.
-    fn assert(&self, message: SignedMessage) -> Result<(), MessageSignerError>;
+    /// content of the serialized message, and that the message is the next one of the session.
+    fn assert(&mut self, message: &SignedMessage) -> Result<(), MessageSignerError>;
+}
+
+/// Identifier and sequence numbers of a session, as tracked by a `MessageSigner`. Each direction
+/// of the session is numbered independently, starting from 0.
+struct Session {
+    id: SessionId,
+    sent: u64,
+    received: u64,
+}
+
+impl Session {
+    fn new(id: SessionId) -> Self {
+        Session {
+            id: id,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    /// Returns the sequence number of the next message to send.
+    fn next_sent(&mut self) -> u64 {
+        let sequence = self.sent;
+        self.sent += 1;
+        sequence
+    }
+
+    /// Check that `sequence` is the number of the next message expected from the peer.
+    fn check_received(&self, sequence: u64) -> Result<(), MessageSignerError> {
+        if sequence == self.received {
+            Ok(())
+        } else {
+            Err(MessageSignerError {
+                kind: MessageSignerErrorKind::OutOfSequence {
+                    expected: self.received,
+                    received: sequence,
+                },
+            })
+        }
+    }
+
+    /// Record that the expected message has been received and verified.
+    fn received(&mut self) {
+        self.received += 1;
+    }
+
+    /// Returns the bytes which bind a message to the session: the session identifier and the
+    /// sequence number of the message.
+    fn binding(&self, sequence: u64) -> Vec<u8> {
+        let mut binding = self.id.clone();
+        binding.extend_from_slice(&sequence.to_be_bytes());
+        binding
+    }
 }
 
 #[derive(Debug)]
 enum MessageSignerErrorKind {
     SignatureDontMatch,
+    OutOfSequence { expected: u64, received: u64 },
 }
 
 /// The error type for signing operations.
@@ -43,7 +158,19 @@ pub struct MessageSignerError {
 
 impl fmt::Display for MessageSignerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Message Signer Error: {:?}", self.kind)
+        match self.kind {
+            MessageSignerErrorKind::SignatureDontMatch => {
+                write!(f, "Message Signer Error: the signature does not match")
+            }
+            MessageSignerErrorKind::OutOfSequence { expected, received } => {
+                write!(
+                    f,
+                    "Message Signer Error: message {} received, expected {}",
+                    received,
+                    expected
+                )
+            }
+        }
     }
 }
 
@@ -53,52 +180,134 @@ impl Error for MessageSignerError {
             MessageSignerErrorKind::SignatureDontMatch => {
                 "The provided signature and the computed signature don't match"
             }
+            MessageSignerErrorKind::OutOfSequence { .. } => {
+                "The message is replayed or out of order"
+            }
+        }
+    }
+}
+
+/// A `MessageSigner` which authenticates messages with HMAC-SHA256.
+pub struct HmacSigner {
+    key: Vec<u8>,
+    session: Session,
+}
+
+impl HmacSigner {
+    /// Create a `HmacSigner` keyed by the secret of an identity, for the session `session_id`.
+    pub fn new(key: BlowfishKey, session_id: SessionId) -> Self {
+        Self {
+            key: key,
+            session: Session::new(session_id),
         }
     }
+
+    fn mac(
+        &self,
+        sequence: u64,
+        message_type: MessageType,
+        serialized_message: &[u8],
+    ) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect(
+            "HMAC accepts keys of any length",
+        );
+        mac.update(&self.session.binding(sequence));
+        mac.update(&[message_type.tag()]);
+        mac.update(serialized_message);
+        mac
+    }
+}
+
+impl MessageSigner for HmacSigner {
+    fn sign<M: Message>(&mut self, message: M) -> Option<SignedMessage> {
+        message.serialize().map(|v| {
+            let sequence = self.session.next_sent();
+            let signature = self.mac(sequence, M::message_type(), &v)
+                .finalize()
+                .into_bytes();
+            SignedMessage {
+                message_type: M::message_type(),
+                sequence: sequence,
+                serialized_message: v,
+                signature: signature.to_vec(),
+            }
+        })
+    }
+
+    fn assert(&mut self, message: &SignedMessage) -> Result<(), MessageSignerError> {
+        self.session.check_received(message.sequence)?;
+        self.mac(
+            message.sequence,
+            message.message_type,
+            &message.serialized_message,
+        ).verify_slice(&message.signature)
+            .map_err(|_| {
+                MessageSignerError { kind: MessageSignerErrorKind::SignatureDontMatch }
+            })?;
+        self.session.received();
+        Ok(())
+    }
 }
 
-/// A `MessageSigner` which uses the Blowfish encryption method to sign the checksum.
+/// A `MessageSigner` which uses the Blowfish encryption method to sign the checksum. Since the
+/// checksum is a single byte, it is easy to forge: prefer `HmacSigner`.
 pub struct BlowfishSigner {
     bf: Blowfish,
+    session: Session,
 }
 
 impl BlowfishSigner {
-    /// Create a `BlowfishSigner` for the given key.
-    pub fn new(bfk: BlowfishKey) -> Self {
-        Self { bf: Blowfish::new(bfk.as_slice()) }
+    /// Create a `BlowfishSigner` for the given key, for the session `session_id`.
+    pub fn new(bfk: BlowfishKey, session_id: SessionId) -> Self {
+        Self {
+            bf: get_cipher(&bfk),
+            session: Session::new(session_id),
+        }
+    }
+
+    fn checksum(
+        &self,
+        sequence: u64,
+        message_type: MessageType,
+        serialized_message: &[u8],
+    ) -> Vec<u8> {
+        let mut xor: u8 = message_type as u8;
+        for byte in self.session.binding(sequence).into_iter().chain(
+            serialized_message.iter().cloned(),
+        )
+        {
+            xor ^= byte;
+            // TODO: Use more than 1 byte
+        }
+        encrypt_block(&self.bf, &[xor, 0, 0, 0, 0, 0, 0, 0])
     }
 }
 
 impl MessageSigner for BlowfishSigner {
-    fn sign<M: Message>(&self, message: M) -> Option<SignedMessage> {
+    fn sign<M: Message>(&mut self, message: M) -> Option<SignedMessage> {
         match message.serialize() {
             Some(v) => {
-                let mut xor: u8 = 0;
-                for byte in v.clone() {
-                    xor = xor ^ byte;
-                    // TODO: Use more than 1 byte
-                }
-                let in_buf = GenericArray::from_slice(&vec![xor, 0, 0, 0, 0, 0, 0, 0]);
-                let mut out_buf = GenericArray::new();
-                self.bf.encrypt_block(&in_buf, &mut out_buf);
+                let sequence = self.session.next_sent();
                 Some(SignedMessage {
+                    message_type: M::message_type(),
+                    sequence: sequence,
+                    signature: self.checksum(sequence, M::message_type(), &v),
                     serialized_message: v,
-                    signature: out_buf.to_vec(),
                 })
             }
             None => None,
         }
     }
 
-    fn assert(&self, message: SignedMessage) -> Result<(), MessageSignerError> {
-        let mut xor: u8 = 0;
-        for byte in message.serialized_message {
-            xor = xor ^ byte;
-        }
-        let in_buf = GenericArray::from_slice(&vec![xor, 0, 0, 0, 0, 0, 0, 0]);
-        let mut out_buf = GenericArray::new();
-        self.bf.encrypt_block(&in_buf, &mut out_buf);
-        if out_buf.to_vec() == message.signature {
+    fn assert(&mut self, message: &SignedMessage) -> Result<(), MessageSignerError> {
+        self.session.check_received(message.sequence)?;
+        let checksum = self.checksum(
+            message.sequence,
+            message.message_type,
+            &message.serialized_message,
+        );
+        if checksum == message.signature {
+            self.session.received();
             Ok(())
         } else {
             Err(MessageSignerError {
@@ -107,3 +316,116 @@ impl MessageSigner for BlowfishSigner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Stat;
+
+    const KEY: &[u8] = b"secret key";
+
+    fn session() -> SessionId {
+        vec![1, 2, 3, 4, 5, 6, 7, 8]
+    }
+
+    fn out_of_sequence(result: Result<(), MessageSignerError>) -> Option<(u64, u64)> {
+        match result {
+            Err(MessageSignerError {
+                    kind: MessageSignerErrorKind::OutOfSequence { expected, received },
+                }) => Some((expected, received)),
+            _ => None,
+        }
+    }
+
+    fn signature_dont_match(result: Result<(), MessageSignerError>) -> bool {
+        matches!(
+            result,
+            Err(MessageSignerError { kind: MessageSignerErrorKind::SignatureDontMatch })
+        )
+    }
+
+    #[test]
+    fn numbers_messages_in_sequence() {
+        let mut sender = HmacSigner::new(KEY.to_vec(), session());
+        let mut receiver = HmacSigner::new(KEY.to_vec(), session());
+        for sequence in 0..3 {
+            let message = sender.sign(Stat::new("file")).unwrap();
+            assert_eq!(message.get_sequence(), sequence);
+            assert!(receiver.assert(&message).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_and_reordered_messages() {
+        let mut sender = HmacSigner::new(KEY.to_vec(), session());
+        let mut receiver = HmacSigner::new(KEY.to_vec(), session());
+        let first = sender.sign(Stat::new("first")).unwrap();
+        let second = sender.sign(Stat::new("second")).unwrap();
+        let third = sender.sign(Stat::new("third")).unwrap();
+        assert_eq!(out_of_sequence(receiver.assert(&second)), Some((0, 1)));
+        assert!(receiver.assert(&first).is_ok());
+        assert_eq!(out_of_sequence(receiver.assert(&first)), Some((1, 0)));
+        assert!(receiver.assert(&second).is_ok());
+        assert!(receiver.assert(&third).is_ok());
+        let error = receiver.assert(&third).unwrap_err();
+        assert_eq!(error.to_string(), "Message Signer Error: message 2 received, expected 3");
+    }
+
+    #[test]
+    fn rejects_messages_of_other_sessions_and_keys() {
+        let mut sender = HmacSigner::new(KEY.to_vec(), session());
+        let message = sender.sign(Stat::new("file")).unwrap();
+        let mut other_session = HmacSigner::new(KEY.to_vec(), vec![8, 7, 6, 5, 4, 3, 2, 1]);
+        let mut other_key = HmacSigner::new(b"other key".to_vec(), session());
+        assert!(signature_dont_match(other_session.assert(&message)));
+        assert!(signature_dont_match(other_key.assert(&message)));
+    }
+
+    #[test]
+    fn rejects_tampered_messages() {
+        let mut sender = HmacSigner::new(KEY.to_vec(), session());
+        let mut receiver = HmacSigner::new(KEY.to_vec(), session());
+        let mut message = sender.sign(Stat::new("file")).unwrap();
+        message.serialized_message[0] ^= 1;
+        assert!(signature_dont_match(receiver.assert(&message)));
+        message.serialized_message[0] ^= 1;
+        message.message_type = MessageType::ListDir;
+        assert!(signature_dont_match(receiver.assert(&message)));
+        message.message_type = MessageType::Stat;
+        assert!(receiver.assert(&message).is_ok());
+    }
+
+    #[test]
+    fn survives_frames() {
+        let mut sender = HmacSigner::new(KEY.to_vec(), session());
+        let mut receiver = HmacSigner::new(KEY.to_vec(), session());
+        sender.sign(Stat::new("skipped")).unwrap();
+        let message = sender.sign(Stat::new("file")).unwrap();
+        let serialized = message.get_message().to_vec();
+        let received = SignedMessage::from_frame(message.into_frame()).unwrap();
+        assert_eq!(received.get_message_type(), MessageType::Stat);
+        assert_eq!(received.get_sequence(), 1);
+        assert_eq!(received.get_message(), &serialized[..]);
+        assert_eq!(out_of_sequence(receiver.assert(&received)), Some((0, 1)));
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        assert!(SignedMessage::from_frame(Frame::new(MessageType::Stat, vec![0; 8])).is_none());
+        let mut payload = vec![0; 9];
+        payload[8] = 32;
+        assert!(SignedMessage::from_frame(Frame::new(MessageType::Stat, payload)).is_none());
+    }
+
+    #[test]
+    fn blowfish_signer_checks_sequence() {
+        let mut sender = BlowfishSigner::new(KEY.to_vec(), session());
+        let mut receiver = BlowfishSigner::new(KEY.to_vec(), session());
+        let first = sender.sign(Stat::new("first")).unwrap();
+        let second = sender.sign(Stat::new("second")).unwrap();
+        assert_eq!(out_of_sequence(receiver.assert(&second)), Some((0, 1)));
+        assert!(receiver.assert(&first).is_ok());
+        assert!(receiver.assert(&second).is_ok());
+        assert_eq!(out_of_sequence(receiver.assert(&second)), Some((2, 1)));
+    }
+}
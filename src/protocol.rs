@@ -0,0 +1,422 @@
+//! This module contains the negotiation of the protocol version and of optional features, which
+//! starts the handshake, before the authentication:
+//!
+//! 1. the server announces the range of versions and the features it supports;
+//! 2. the client chooses the highest version supported by both, and the features it wants among
+//!    those of the server;
+//! 3. the server accepts the choice, or rejects it with a reason and closes the connection.
+//!
+//! Servers and clients can thus be upgraded separately, as long as they share a version.
+
+use crate::message::MessageType;
+use crate::rfs_common::CHALLENGE_LENGTH;
+use std::error::Error;
+use std::fmt;
+
+/// Version of the protocol spoken by this implementation.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version of the protocol still spoken by this implementation.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Feature allowing to open files on the server, and to address them by handle.
+pub const HANDLES: &str = "handles";
+/// Feature encrypting the frames exchanged after the authentication, see `frame_cipher`. Clients
+/// choose it if their configuration enables encryption, and servers whose configuration enables
+/// encryption reject the clients which do not.
+pub const ENCRYPTION: &str = "encryption";
+/// Optional features supported by this implementation.
+pub const FEATURES: [&str; 2] = [HANDLES, ENCRYPTION];
+
+const GREETING: &str = "RFS protocol: ";
+const CHOICE: &str = "Protocol: ";
+const ACCEPTED: &str = "Protocol accepted: ";
+const REJECTED: &str = "Protocol rejected: ";
+/// First line sent by servers which do not negotiate the protocol.
+const LEGACY_GREETING: &str = "Please identify yourself";
+
+/// The version and features of the protocol agreed on by a client and a server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Protocol {
+    version: u32,
+    features: Vec<String>,
+}
+
+impl Protocol {
+    fn new(version: u32, features: Vec<String>) -> Self {
+        Protocol {
+            version: version,
+            features: features,
+        }
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_features(&self) -> &Vec<String> {
+        &self.features
+    }
+
+    /// Returns whether both sides agreed to use the feature `name`.
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.iter().any(|f| f == name)
+    }
+
+    /// Returns the line by which the server accepted the protocol, without its end of line. It is
+    /// bound to the proof of the server and to the keys of the session, so that a negotiation
+    /// altered on the way fails the authentication.
+    pub(crate) fn get_accepted_line(&self) -> String {
+        format!("{}{}; features: {}", ACCEPTED, self.version, self.features.join(","))
+    }
+
+    /// Returns whether requests of type `message_type` can be sent in the session, i.e. whether
+    /// the feature they belong to, if any, was agreed on.
+    pub fn allows(&self, message_type: MessageType) -> bool {
+        match feature_of(message_type) {
+            Some(feature) => self.has_feature(feature),
+            None => true,
+        }
+    }
+}
+
+/// Returns the optional feature the requests of type `message_type` belong to.
+fn feature_of(message_type: MessageType) -> Option<&'static str> {
+    match message_type {
+        MessageType::Open |
+        MessageType::Close |
+        MessageType::ReadHandle |
+        MessageType::WriteHandle |
+        MessageType::StatHandle |
+        MessageType::TruncateHandle => Some(HANDLES),
+        _ => None,
+    }
+}
+
+/// Returns the error of a client receiving a challenge of `length` bytes, which it can not answer.
+pub(crate) fn bad_challenge(length: usize) -> ProtocolError {
+    ProtocolError::new(ProtocolErrorKind::BadChallenge(length))
+}
+
+/// Returns the error of a client using `feature`, which was not agreed on with the server.
+pub(crate) fn missing_feature(feature: &str) -> ProtocolError {
+    ProtocolError::new(ProtocolErrorKind::MissingFeature(feature.to_string()))
+}
+
+/// Returns the first line sent by a server: the versions and the features it supports.
+pub(crate) fn greeting() -> String {
+    format!(
+        "{}{}-{}; features: {}\n",
+        GREETING,
+        MIN_PROTOCOL_VERSION,
+        PROTOCOL_VERSION,
+        FEATURES.join(",")
+    )
+}
+
+/// Choose the protocol of the session from the greeting of the server, with `ENCRYPTION` if
+/// `encryption`. Returns the protocol and the line to send to the server.
+pub(crate) fn choose(
+    greeting: &str,
+    encryption: bool,
+) -> Result<(Protocol, String), ProtocolError> {
+    if greeting.starts_with(LEGACY_GREETING) {
+        return Err(ProtocolError::new(ProtocolErrorKind::LegacyServer));
+    }
+    let (versions, features) = parse_line(greeting, GREETING)?;
+    let (min, max) = match versions.split_once('-') {
+        Some((min, max)) => (parse_version(greeting, min)?, parse_version(greeting, max)?),
+        None => return Err(malformed(greeting)),
+    };
+    let version = max.min(PROTOCOL_VERSION);
+    if version < min.max(MIN_PROTOCOL_VERSION) {
+        return Err(ProtocolError::new(ProtocolErrorKind::UnsupportedVersion {
+            version: version,
+            min: min,
+            max: max,
+        }));
+    }
+    let mut features = supported(features);
+    if encryption && !features.iter().any(|f| f == ENCRYPTION) {
+        return Err(missing_feature(ENCRYPTION));
+    }
+    features.retain(|f| encryption || f != ENCRYPTION);
+    let protocol = Protocol::new(version, features);
+    let choice = format!("{}{}; features: {}\n", CHOICE, version, protocol.features.join(","));
+    Ok((protocol, choice))
+}
+
+/// Check the choice of a client, which must include `ENCRYPTION` if `encryption`. Returns the
+/// protocol of the session and the line accepting it, or the error and the line rejecting it.
+pub(crate) fn accept(
+    choice: &str,
+    encryption: bool,
+) -> Result<(Protocol, String), (ProtocolError, String)> {
+    let reject = |e: ProtocolError| {
+        let line = format!("{}{}\n", REJECTED, e.get_reason());
+        (e, line)
+    };
+    let (version, features) = parse_line(choice, CHOICE).map_err(&reject)?;
+    let version = parse_version(choice, version).map_err(&reject)?;
+    if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION {
+        return Err(reject(ProtocolError::new(ProtocolErrorKind::UnsupportedVersion {
+            version: version,
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        })));
+    }
+    let protocol = Protocol::new(version, supported(features));
+    if encryption && !protocol.has_feature(ENCRYPTION) {
+        return Err(reject(ProtocolError::new(ProtocolErrorKind::EncryptionRequired)));
+    }
+    let line = protocol.get_accepted_line() + "\n";
+    Ok((protocol, line))
+}
+
+/// Returns the protocol of the session, from the answer of the server to `choice`, the protocol
+/// chosen by the client. The server must accept the chosen version, with some of the chosen
+/// features, and must keep `ENCRYPTION` if it was chosen.
+pub(crate) fn parse_answer(answer: &str, choice: &Protocol) -> Result<Protocol, ProtocolError> {
+    if let Some(reason) = answer.strip_prefix(REJECTED) {
+        return Err(ProtocolError::new(
+            ProtocolErrorKind::Rejected(reason.trim_end().to_string()),
+        ));
+    }
+    let (version, features) = parse_line(answer, ACCEPTED)?;
+    let version = parse_version(answer, version)?;
+    if version != choice.version || features.iter().any(|f| !choice.has_feature(f)) {
+        return Err(ProtocolError::new(
+            ProtocolErrorKind::NotChosen(answer.trim_end().to_string()),
+        ));
+    }
+    let protocol = Protocol::new(version, features.into_iter().map(String::from).collect());
+    if choice.has_feature(ENCRYPTION) && !protocol.has_feature(ENCRYPTION) {
+        return Err(missing_feature(ENCRYPTION));
+    }
+    Ok(protocol)
+}
+
+/// Split `line`, of the form `<prefix><value>; features: <feature>,...`, into its value and its
+/// features.
+fn parse_line<'a>(line: &'a str, prefix: &str) -> Result<(&'a str, Vec<&'a str>), ProtocolError> {
+    let fields = line.strip_prefix(prefix).ok_or(malformed(line))?;
+    let (value, features) = fields
+        .trim_end()
+        .split_once("; features:")
+        .ok_or(malformed(line))?;
+    let features = features
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .collect();
+    Ok((value.trim(), features))
+}
+
+fn parse_version(line: &str, version: &str) -> Result<u32, ProtocolError> {
+    version.trim().parse().map_err(|_| malformed(line))
+}
+
+/// Returns the features of `features` supported by this implementation.
+fn supported(features: Vec<&str>) -> Vec<String> {
+    features
+        .into_iter()
+        .filter(|f| FEATURES.contains(f))
+        .map(String::from)
+        .collect()
+}
+
+fn malformed(line: &str) -> ProtocolError {
+    ProtocolError::new(ProtocolErrorKind::Malformed(line.trim_end().to_string()))
+}
+
+#[derive(Debug)]
+enum ProtocolErrorKind {
+    Malformed(String),
+    UnsupportedVersion { version: u32, min: u32, max: u32 },
+    Rejected(String),
+    LegacyServer,
+    MissingFeature(String),
+    NotChosen(String),
+    EncryptionRequired,
+    BadChallenge(usize),
+}
+
+/// The error type for the negotiation of the protocol.
+#[derive(Debug)]
+pub struct ProtocolError {
+    kind: ProtocolErrorKind,
+}
+
+impl ProtocolError {
+    fn new(kind: ProtocolErrorKind) -> Self {
+        ProtocolError { kind: kind }
+    }
+
+    /// Returns the reason of the error, as sent to the peer.
+    fn get_reason(&self) -> String {
+        match self.kind {
+            ProtocolErrorKind::Malformed(ref line) => format!("malformed line \"{}\"", line),
+            ProtocolErrorKind::UnsupportedVersion { version, min, max } => {
+                format!("unsupported version {} (supported: {}-{})", version, min, max)
+            }
+            ProtocolErrorKind::Rejected(ref reason) => reason.clone(),
+            ProtocolErrorKind::LegacyServer => "legacy server".to_string(),
+            ProtocolErrorKind::MissingFeature(ref feature) => {
+                format!("feature {} not negotiated", feature)
+            }
+            ProtocolErrorKind::NotChosen(ref line) => {
+                format!("answer \"{}\" differs from the choice of the client", line)
+            }
+            ProtocolErrorKind::EncryptionRequired => "encryption required".to_string(),
+            ProtocolErrorKind::BadChallenge(length) => {
+                format!("challenge of {} bytes instead of {}", length, CHALLENGE_LENGTH)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Protocol Error: {}", self.get_reason())
+    }
+}
+
+impl Error for ProtocolError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ProtocolErrorKind::Malformed(_) => "The negotiation of the protocol is malformed",
+            ProtocolErrorKind::UnsupportedVersion { .. } => {
+                "The client and the server have no protocol version in common"
+            }
+            ProtocolErrorKind::Rejected(_) => "The server rejected the protocol of the client",
+            ProtocolErrorKind::LegacyServer => {
+                "The server is too old to negotiate the version of the protocol"
+            }
+            ProtocolErrorKind::MissingFeature(_) => {
+                "The feature was not negotiated with the server"
+            }
+            ProtocolErrorKind::NotChosen(_) => {
+                "The server accepted a protocol the client did not choose"
+            }
+            ProtocolErrorKind::EncryptionRequired => {
+                "The server requires the frames to be encrypted"
+            }
+            ProtocolErrorKind::BadChallenge(_) => "The challenge of the server has a wrong length",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(result: Result<(Protocol, String), ProtocolError>) -> String {
+        result.unwrap_err().get_reason()
+    }
+
+    #[test]
+    fn negotiates_the_greeting_of_the_server() {
+        let (client, choice) = choose(&greeting(), true).unwrap();
+        assert_eq!(client.get_version(), PROTOCOL_VERSION);
+        assert!(client.has_feature(HANDLES));
+        assert!(client.has_feature(ENCRYPTION));
+        let (server, answer) = accept(&choice, true).unwrap();
+        assert_eq!(server, client);
+        assert_eq!(parse_answer(&answer, &client).unwrap(), client);
+    }
+
+    #[test]
+    fn chooses_the_highest_common_version() {
+        let greeting = format!("{}1-{}; features: \n", GREETING, PROTOCOL_VERSION + 5);
+        let (protocol, choice) = choose(&greeting, false).unwrap();
+        assert_eq!(protocol.get_version(), PROTOCOL_VERSION);
+        assert!(protocol.get_features().is_empty());
+        assert_eq!(choice, format!("{}{}; features: \n", CHOICE, PROTOCOL_VERSION));
+
+        let newer = format!("{}{}-{}; features: \n", GREETING, PROTOCOL_VERSION + 1, 9);
+        assert!(reason(choose(&newer, false)).starts_with("unsupported version"));
+    }
+
+    #[test]
+    fn keeps_the_features_supported_by_both() {
+        let greeting = format!("{}1-1; features: compression, {} ,\n", GREETING, HANDLES);
+        let (protocol, choice) = choose(&greeting, false).unwrap();
+        assert_eq!(protocol.get_features(), &vec![HANDLES.to_string()]);
+        assert_eq!(choice, format!("{}1; features: {}\n", CHOICE, HANDLES));
+
+        let choice = format!("{}1; features: compression\n", CHOICE);
+        let (protocol, _) = accept(&choice, false).unwrap();
+        assert!(protocol.get_features().is_empty());
+    }
+
+    #[test]
+    fn negotiates_encryption() {
+        let (protocol, choice) = choose(&greeting(), false).unwrap();
+        assert!(!protocol.has_feature(ENCRYPTION));
+        let (error, line) = accept(&choice, true).unwrap_err();
+        assert_eq!(error.get_reason(), "encryption required");
+        assert_eq!(line, format!("{}encryption required\n", REJECTED));
+        let (protocol, _) = accept(&choice, false).unwrap();
+        assert!(!protocol.has_feature(ENCRYPTION));
+
+        let (protocol, choice) = choose(&greeting(), true).unwrap();
+        let (accepted, answer) = accept(&choice, false).unwrap();
+        assert!(accepted.has_feature(ENCRYPTION));
+        assert_eq!(parse_answer(&answer, &protocol).unwrap(), protocol);
+        let clear = format!("{}1; features: {}\n", ACCEPTED, HANDLES);
+        let error = parse_answer(&clear, &protocol).unwrap_err();
+        assert_eq!(error.get_reason(), format!("feature {} not negotiated", ENCRYPTION));
+
+        let greeting = format!("{}1-1; features: {}\n", GREETING, HANDLES);
+        assert!(reason(choose(&greeting, true)).contains(ENCRYPTION));
+    }
+
+    #[test]
+    fn rejects_malformed_and_legacy_greetings() {
+        assert!(reason(choose("RFS protocol: 1; features: \n", false)).starts_with("malformed"));
+        assert!(reason(choose("RFS protocol: a-b; features: \n", false)).starts_with("malformed"));
+        assert!(reason(choose("hello\n", false)).starts_with("malformed"));
+        assert_eq!(reason(choose("Please identify yourself\n", false)), "legacy server");
+    }
+
+    #[test]
+    fn rejects_unsupported_choices() {
+        let choice = format!("{}{}; features: \n", CHOICE, PROTOCOL_VERSION + 1);
+        let (error, line) = accept(&choice, false).unwrap_err();
+        assert!(error.get_reason().starts_with("unsupported version"));
+        assert_eq!(line, format!("{}{}\n", REJECTED, error.get_reason()));
+        let chosen = Protocol::new(PROTOCOL_VERSION + 1, Vec::new());
+        assert_eq!(parse_answer(&line, &chosen).unwrap_err().get_reason(), error.get_reason());
+
+        let (_, line) = accept("Protocol: one; features: \n", false).unwrap_err();
+        assert!(line.starts_with(REJECTED));
+    }
+
+    #[test]
+    fn rejects_answers_beyond_the_choice() {
+        let (protocol, choice) = choose(&greeting(), false).unwrap();
+        let (accepted, answer) = accept(&choice, false).unwrap();
+        assert_eq!(answer, accepted.get_accepted_line() + "\n");
+        assert_eq!(parse_answer(&answer, &protocol).unwrap(), protocol);
+
+        let newer = format!("{}{}; features: \n", ACCEPTED, PROTOCOL_VERSION + 1);
+        let error = parse_answer(&newer, &protocol).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Protocol Error: answer \"{}\" differs from the choice of the client",
+                    newer.trim_end())
+        );
+        let encrypted = format!("{}1; features: {},{}\n", ACCEPTED, HANDLES, ENCRYPTION);
+        assert!(parse_answer(&encrypted, &protocol).unwrap_err().get_reason().contains(ENCRYPTION));
+    }
+
+    #[test]
+    fn allows_requests_of_negotiated_features() {
+        let with_handles = Protocol::new(1, vec![HANDLES.to_string()]);
+        let without = Protocol::new(1, Vec::new());
+        for message_type in &[MessageType::Open, MessageType::ReadHandle, MessageType::Close] {
+            assert!(with_handles.allows(*message_type));
+            assert!(!without.allows(*message_type));
+        }
+        assert!(without.allows(MessageType::ReadFile));
+        assert!(without.allows(MessageType::Stat));
+    }
+}
@@ -0,0 +1,260 @@
+//! An asynchronous variant of `rfs_client::RfsClientSession`, built on tokio, to embed a client in
+//! an asynchronous service.
+
+use blowfish::Blowfish;
+use crate::config::{RfsConfig, Config, Field};
+use crate::message::{Message, Reply};
+use crate::message::{WriteFile, WriteFileReply, ReadFile, ReadFileReply, Stat, StatReply, ListDir,
+                     ListDirReply, Mkdir, MkdirReply, Rmdir, RmdirReply, Unlink, UnlinkReply,
+                     Rename, RenameReply, Truncate, TruncateReply, SetTimes, SetTimesReply,
+                     FileStat, DirEntry, Timestamp};
+use crate::message_signer::DefaultSigner;
+use crate::frame_cipher::FrameCipher;
+use crate::protocol::{self, Protocol};
+use crate::rfs_client::{RfsClientError, RfsClientErrorKind, get_challenge_response,
+                        check_verdict, request_frame, reply_of_frame};
+use crate::rfs_common::{Identity, Named, get_cipher, generate_challenge, challenge_line,
+                        parse_challenge, read_handshake_line_async, FrameReader, FrameWriter};
+use crate::rfs_server::AUTH_SUCCESS;
+use std::io::Error as IoError;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+pub struct AsyncRfsClientSession {
+    stream: BufReader<TcpStream>,
+    identity: Field,
+    server: Field,
+    encryption: bool,
+    bf: Blowfish,
+    signer: Option<DefaultSigner>,
+    frame_reader: FrameReader,
+    frame_writer: FrameWriter,
+    protocol: Option<Protocol>,
+}
+
+impl AsyncRfsClientSession {
+    pub async fn new(server_name: String, client_name: String, config: RfsConfig) -> Option<Self> {
+        let id = match config.get_from_name(client_name) {
+            Ok(id) => id.clone(),
+            Err(e) => {
+                warn!("Could not create AsyncRfsClientSession. Reason: {}", e);
+                return None;
+            }
+        };
+        let server = config.get_from_name(server_name.clone()).ok().cloned();
+        let (address, server) = match (config.get_server_address(server_name), server) {
+            (Some(address), Some(server)) => (address, server),
+            _ => {
+                warn!("Could not create AsyncRfsClientSession.");
+                return None;
+            }
+        };
+        if server.get_tls().is_some() {
+            warn!("Could not create AsyncRfsClientSession. Reason: the server requires TLS");
+            return None;
+        }
+        match TcpStream::connect(address).await {
+            Ok(stream) => {
+                info!("Connection successful");
+                Some(AsyncRfsClientSession {
+                    stream: BufReader::new(stream),
+                    bf: get_cipher(id.get_secret()),
+                    signer: None,
+                    identity: id,
+                    server: server,
+                    encryption: config.is_encryption_enabled(),
+                    frame_reader: FrameReader::new(),
+                    frame_writer: FrameWriter::new(),
+                    protocol: None,
+                })
+            }
+            Err(e) => {
+                warn!("Could not create AsyncRfsClientSession (Can not connect). Reason: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Answer the challenge of the server, then check that the server answers ours. Must be
+    /// called before any request. The session is aborted if the server can not prove that it
+    /// knows the key of `Field::Server`.
+    pub async fn connect(&mut self) -> Result<(), RfsClientError> {
+        let checked = self.authenticate().await;
+        if checked.is_err() {
+            if let Err(e) = self.disconnect().await {
+                warn!("Could not shutdown connection: {}", e);
+            }
+        }
+        checked
+    }
+
+    async fn authenticate(&mut self) -> Result<(), RfsClientError> {
+        let transport = |e: IoError| {
+            warn!("Authentication failure. Reason: {}", e);
+            RfsClientError::new(RfsClientErrorKind::Transport)
+        };
+        let mut line = String::new();
+        read_handshake_line_async(&mut self.stream, &mut line).await.map_err(transport)?;
+        let (chosen, choice) = protocol::choose(&line, self.encryption)?;
+        self.stream.get_mut().write_all(choice.as_bytes()).await.map_err(transport)?;
+        line.clear();
+        read_handshake_line_async(&mut self.stream, &mut line).await.map_err(transport)?;
+        let protocol = protocol::parse_answer(&line, &chosen)?;
+        info!("Protocol negotiated: {:?}", protocol);
+        let encryption = protocol.has_feature(protocol::ENCRYPTION);
+        let protocol_line = protocol.get_accepted_line();
+        self.protocol = Some(protocol);
+
+        line.clear();
+        read_handshake_line_async(&mut self.stream, &mut line).await.map_err(transport)?;
+        line.clear();
+        read_handshake_line_async(&mut self.stream, &mut line).await.map_err(transport)?;
+        let c = match parse_challenge(&line) {
+            Some(c) => c,
+            None => {
+                warn!("Could not get challenge");
+                return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+            }
+        };
+        info!("Challenge is: {:?}", c);
+        let client_challenge = generate_challenge();
+        let response = base64::encode(&get_challenge_response(c.clone(), &self.bf)?);
+        let answer = self.identity.get_name().clone() + "\n" + &response + "\n" +
+            &challenge_line(&client_challenge);
+        self.stream.get_mut().write_all(answer.as_bytes()).await.map_err(transport)?;
+        let mut verdict = String::new();
+        let mut proof = String::new();
+        read_handshake_line_async(&mut self.stream, &mut verdict).await.map_err(transport)?;
+        if verdict == AUTH_SUCCESS {
+            read_handshake_line_async(&mut self.stream, &mut proof).await.map_err(transport)?;
+        }
+        info!("Server answered: {}", verdict.trim());
+        check_verdict(&self.identity, &self.server, &c, &client_challenge, &protocol_line,
+                      &verdict, &proof)?;
+        if encryption {
+            let secret = self.identity.get_secret();
+            self.frame_writer.set_cipher(
+                FrameCipher::client_to_server(secret, &c, &client_challenge, &protocol_line),
+            );
+            self.frame_reader.set_cipher(
+                FrameCipher::server_to_client(secret, &c, &client_challenge, &protocol_line),
+            );
+        }
+        self.signer = Some(DefaultSigner::new(self.identity.get_secret().clone(), c));
+        Ok(())
+    }
+
+    /// Returns the protocol negotiated with the server, once connected.
+    pub fn get_protocol(&self) -> Option<&Protocol> {
+        self.protocol.as_ref()
+    }
+
+    pub async fn disconnect(&mut self) -> Result<(), IoError> {
+        info!("Shutdown connection");
+        self.stream.get_mut().shutdown().await
+    }
+
+    /// Write `content` at `position` in the remote file `filename`. Returns the number of bytes
+    /// written by the server.
+    pub async fn write_at(
+        &mut self,
+        filename: &str,
+        position: u64,
+        content: Vec<u8>,
+    ) -> Result<u64, RfsClientError> {
+        let reply: WriteFileReply = self.request(WriteFile::new(content, position, filename))
+            .await?;
+        Ok(reply.get_written())
+    }
+
+    /// Read at most `length` bytes of the remote file `filename`, starting at `position`. Returns
+    /// the data read, and whether the end of the file has been reached.
+    pub async fn read_at(
+        &mut self,
+        filename: &str,
+        position: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), RfsClientError> {
+        let reply: ReadFileReply = self.request(ReadFile::new(filename, position, length))
+            .await?;
+        let eof = reply.is_eof();
+        Ok((reply.into_data(), eof))
+    }
+
+    /// Returns the metadata of the remote file `path`.
+    pub async fn stat(&mut self, path: &str) -> Result<FileStat, RfsClientError> {
+        let reply: StatReply = self.request(Stat::new(path)).await?;
+        reply.into_stat().ok_or(
+            RfsClientError::new(RfsClientErrorKind::Serialization),
+        )
+    }
+
+    /// Returns the entries of the remote directory `path`.
+    pub async fn list_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, RfsClientError> {
+        let reply: ListDirReply = self.request(ListDir::new(path)).await?;
+        Ok(reply.into_entries())
+    }
+
+    /// Create the remote directory `path`.
+    pub async fn mkdir(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: MkdirReply = self.request(Mkdir::new(path)).await?;
+        Ok(())
+    }
+
+    /// Remove the empty remote directory `path`.
+    pub async fn rmdir(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: RmdirReply = self.request(Rmdir::new(path)).await?;
+        Ok(())
+    }
+
+    /// Remove the remote file `path`.
+    pub async fn unlink(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: UnlinkReply = self.request(Unlink::new(path)).await?;
+        Ok(())
+    }
+
+    /// Rename the remote file `from` into `to`.
+    pub async fn rename(&mut self, from: &str, to: &str) -> Result<(), RfsClientError> {
+        let _: RenameReply = self.request(Rename::new(from, to)).await?;
+        Ok(())
+    }
+
+    /// Truncate (or extend with zeros) the remote file `path` to `length` bytes.
+    pub async fn truncate(&mut self, path: &str, length: u64) -> Result<(), RfsClientError> {
+        let _: TruncateReply = self.request(Truncate::new(path, length)).await?;
+        Ok(())
+    }
+
+    /// Set the access and modification times of the remote file `path`.
+    pub async fn set_times(
+        &mut self,
+        path: &str,
+        accessed: Timestamp,
+        modified: Timestamp,
+    ) -> Result<(), RfsClientError> {
+        let _: SetTimesReply = self.request(SetTimes::new(path, accessed, modified)).await?;
+        Ok(())
+    }
+
+    /// Sign and send a request, then wait for the signed reply of the server.
+    async fn request<M: Message, R: Reply>(&mut self, message: M) -> Result<R, RfsClientError> {
+        let signer = self.signer.as_mut().ok_or(RfsClientError::new(
+            RfsClientErrorKind::NotConnected,
+        ))?;
+        let frame = request_frame(signer, message)?;
+        if let Err(e) = self.frame_writer
+            .write_frame_async(self.stream.get_mut(), frame)
+            .await
+        {
+            warn!("Could not send request. Reason: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        match self.frame_reader.read_frame_async(&mut self.stream).await {
+            Ok(frame) => reply_of_frame(signer, frame),
+            Err(e) => {
+                warn!("Could not receive reply. Reason: {}", e);
+                Err(RfsClientError::new(RfsClientErrorKind::Transport))
+            }
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! An asynchronous variant of `rfs_server::RfsServer`, built on tokio. Each client is served by
+//! its own task, so that idle connections do not hold a thread.
+
+use crate::config::{RfsConfig, Config, Field};
+use crate::message_signer::DefaultSigner;
+use crate::frame_cipher::FrameCipher;
+use crate::live_config::LiveConfig;
+use crate::protocol::{self, Protocol};
+use crate::rfs_common::{Challenge, SessionId, FrameReader, FrameWriter, Identity, Named,
+                        welcome, generate_challenge, parse_challenge, server_proof,
+                        read_handshake_line_async};
+use crate::rfs_server::{RequestHandler, AUTH_SUCCESS, AUTH_FAILURE, id_request, proof_line,
+                        remove_newline, client_expected_challenge_response, export_root,
+                        client_sandbox, client_permissions};
+use crate::sandbox::Sandbox;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task;
+
+/// Default maximal number of clients served concurrently. Idle clients only hold a task, so the
+/// limit is much higher than the one of `rfs_server::RfsServer`.
+pub const DEFAULT_MAX_CLIENTS: usize = 4096;
+
+pub struct AsyncRfsServer {
+    name: String,
+    identity: Field,
+    config: LiveConfig,
+    sandbox: Sandbox,
+    listener: TcpListener,
+    connections: Arc<Semaphore>,
+}
+
+impl AsyncRfsServer {
+    /// Create the server named `name` in `config`, listening on the address and port of its
+    /// entry.
+    pub async fn new(name: String, config: RfsConfig) -> Option<Self> {
+        AsyncRfsServer::create(name, config, None).await
+    }
+
+    /// Create the server named `name` in `config`, listening on `socket` (e.g. `0.0.0.0:4242`)
+    /// instead of the address and port of its entry.
+    pub async fn bound_to(name: String, config: RfsConfig, socket: String) -> Option<Self> {
+        AsyncRfsServer::create(name, config, Some(socket)).await
+    }
+
+    async fn create(name: String, config: RfsConfig, socket: Option<String>) -> Option<Self> {
+        let my_conf = match config.get_from_name(name) {
+            Ok(f) => f.clone(),
+            Err(e) => {
+                error!("Can not create AsyncRfsServer. Reason: {}", e);
+                return None;
+            }
+        };
+        match my_conf.clone() {
+            Field::Server {
+                name,
+                key: _,
+                address: _,
+                port: _,
+                tls: Some(_),
+                root: _,
+            } => {
+                error!("Can not create AsyncRfsServer. Reason: {} requires TLS", name);
+                None
+            }
+            Field::Server {
+                name,
+                key: _,
+                address,
+                port,
+                tls: None,
+                root: _,
+            } => {
+                welcome(my_conf.clone());
+                let sandbox = match export_root(&my_conf) {
+                    Ok(sandbox) => sandbox,
+                    Err(e) => {
+                        error!("Can not create AsyncRfsServer. Reason: {}", e);
+                        return None;
+                    }
+                };
+                let socket = socket.unwrap_or(address + ":" + &port);
+                match TcpListener::bind(socket).await {
+                    Ok(l) => Some(AsyncRfsServer {
+                        name: name,
+                        identity: my_conf,
+                        config: LiveConfig::new(config),
+                        sandbox: sandbox,
+                        listener: l,
+                        connections: Arc::new(Semaphore::new(DEFAULT_MAX_CLIENTS)),
+                    }),
+                    Err(e) => {
+                        error!("Can not create AsyncRfsServer. Reason: {}", e);
+                        None
+                    }
+                }
+            }
+            Field::Client {
+                name,
+                key: _,
+                root: _,
+                permissions: _,
+            } => {
+                error!("Item {} is a client", name);
+                None
+            }
+        }
+    }
+
+    /// Returns the configuration of the server, which can be reloaded while it serves clients.
+    /// The identity, address and export root of the server are not reloaded.
+    pub fn get_config(&self) -> &LiveConfig {
+        &self.config
+    }
+
+    /// Set the maximal number of clients served concurrently. Further clients wait until a
+    /// client disconnects. Must be called before `AsyncRfsServer::listen`.
+    pub fn set_max_clients(&mut self, max_clients: usize) {
+        self.connections = Arc::new(Semaphore::new(max_clients));
+    }
+
+    /// Accept clients forever, serving each of them on its own task.
+    pub async fn listen(&self) {
+        info!("Server {} waiting for clients", self.name);
+        loop {
+            let permit = match self.connections.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(e) => {
+                    error!("Can not accept clients anymore. Reason: {}", e);
+                    return;
+                }
+            };
+            match self.listener.accept().await {
+                Ok((socket, addr)) => {
+                    info!("new tcp client: {:?}", addr);
+                    let identity = self.identity.clone();
+                    let config = self.config.clone();
+                    let sandbox = self.sandbox.clone();
+                    tokio::spawn(async move {
+                        handle_client(identity, config, sandbox, socket).await;
+                        info!("Connection with {:?} closed", addr);
+                        drop(permit);
+                    });
+                }
+                Err(e) => warn!("error: {:?}", e),
+            }
+        }
+    }
+}
+
+async fn handle_client(
+    identity: Field,
+    live_config: LiveConfig,
+    sandbox: Sandbox,
+    stream: TcpStream,
+) {
+    let mut stream = BufReader::new(stream);
+    let config = live_config.get();
+    let protocol = match negotiate(&mut stream, config.is_encryption_enabled()).await {
+        Some(protocol) => protocol,
+        None => return,
+    };
+    let protocol_line = protocol.get_accepted_line();
+    match auth_client(&identity, &config, &mut stream, &protocol_line).await {
+        Some((client, challenge, client_challenge)) => {
+            info!("Client {} authenticated.", client.get_name());
+            let mut frame_reader = FrameReader::new();
+            let mut frame_writer = FrameWriter::new();
+            if protocol.has_feature(protocol::ENCRYPTION) {
+                let secret = client.get_secret();
+                frame_reader.set_cipher(FrameCipher::client_to_server(
+                    secret,
+                    &challenge,
+                    &client_challenge,
+                    &protocol_line,
+                ));
+                frame_writer.set_cipher(FrameCipher::server_to_client(
+                    secret,
+                    &challenge,
+                    &client_challenge,
+                    &protocol_line,
+                ));
+            }
+            let sandbox = match client_sandbox(&sandbox, &client) {
+                Some(sandbox) => sandbox,
+                None => return,
+            };
+            let signer = DefaultSigner::new(client.get_secret().clone(), challenge);
+            let handler =
+                RequestHandler::new(signer, sandbox, client_permissions(&client), protocol);
+            let session = Session {
+                live_config: live_config,
+                client: client,
+            };
+            serve_requests(&mut stream, session, handler, frame_reader, frame_writer).await;
+        }
+        None => warn!("Authentication failure"),
+    }
+}
+
+/// An authenticated client, and the configuration which may revoke it.
+struct Session {
+    live_config: LiveConfig,
+    client: Field,
+}
+
+/// Read requests from an authenticated client until the connection is closed, or the client is
+/// revoked. Requests are executed on the blocking pool of tokio, since they access the
+/// filesystem.
+async fn serve_requests<S>(
+    stream: &mut BufReader<S>,
+    session: Session,
+    handler: RequestHandler<DefaultSigner>,
+    mut frame_reader: FrameReader,
+    mut frame_writer: FrameWriter,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut handler = handler;
+    loop {
+        let frame = match frame_reader.read_frame_async(stream).await {
+            Ok(frame) => frame,
+            Err(ref e) if e.is_closed() => return,
+            Err(e) => {
+                warn!("Dropping client. Reason: {}", e);
+                return;
+            }
+        };
+        if session.live_config.is_revoked(&session.client) {
+            warn!("Dropping client {}. Reason: revoked", session.client.get_name());
+            return;
+        }
+        let executed = task::spawn_blocking(move || {
+            let reply = handler.handle(frame);
+            (handler, reply)
+        }).await;
+        let reply = match executed {
+            Ok((h, Some(reply))) => {
+                handler = h;
+                reply
+            }
+            Ok((_, None)) => return,
+            Err(e) => {
+                warn!("Dropping client. Reason: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = frame_writer.write_frame_async(stream.get_mut(), reply).await {
+            warn!("Could not send reply. Reason: {}", e);
+            return;
+        }
+    }
+}
+
+/// Asynchronous counterpart of the negotiation of the protocol by `rfs_server::RfsServer`.
+async fn negotiate<S>(stream: &mut BufReader<S>, encryption: bool) -> Option<Protocol>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(e) = stream.get_mut().write_all(protocol::greeting().as_bytes()).await {
+        warn!("Protocol greeting not sent: {}", e);
+        return None;
+    }
+    let mut choice = String::new();
+    if let Err(e) = read_handshake_line_async(stream, &mut choice).await {
+        warn!("Could not read protocol choice: {}", e);
+        return None;
+    }
+    let (answer, protocol) = match protocol::accept(&choice, encryption) {
+        Ok((protocol, line)) => (line, Some(protocol)),
+        Err((e, line)) => {
+            warn!("Protocol rejected. Reason: {}", e);
+            (line, None)
+        }
+    };
+    if let Err(e) = stream.get_mut().write_all(answer.as_bytes()).await {
+        warn!("Protocol answer not sent: {}", e);
+        return None;
+    }
+    if let Some(ref protocol) = protocol {
+        info!("Protocol negotiated: {:?}", protocol);
+    }
+    protocol
+}
+
+/// Asynchronous counterpart of the challenge performed by `rfs_server::RfsServer`.
+async fn auth_client<S>(
+    identity: &Field,
+    config: &RfsConfig,
+    stream: &mut BufReader<S>,
+    protocol_line: &str,
+) -> Option<(Field, SessionId, Challenge)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let challenge = generate_challenge();
+    info!("Challenge proposed: {:?}", challenge);
+    if let Err(e) = stream.get_mut().write_all(id_request(&challenge).as_bytes()).await {
+        warn!("ID_REQ not sent: {}", e);
+        return None;
+    }
+
+    let mut reader_buffer = String::new();
+    if let Err(e) = read_handshake_line_async(stream, &mut reader_buffer).await {
+        warn!("Could not read identity: {}", e);
+        return None;
+    }
+    let client = match config.get_from_name(remove_newline(reader_buffer.clone())) {
+        Ok(c @ &Field::Client { .. }) => c.clone(),
+        Ok(f) => {
+            warn!("Item {} is not a client", f.get_name());
+            return None;
+        }
+        Err(_) => return None,
+    };
+    info!("Identity pretended: {}", client.get_name());
+    let (_, challenge_response_exp) =
+        client_expected_challenge_response(&client, challenge.clone());
+
+    reader_buffer.clear();
+    if let Err(e) = read_handshake_line_async(stream, &mut reader_buffer).await {
+        warn!("Could not read challenge response: {}", e);
+        return None;
+    }
+    let challenge_response_buf: Challenge =
+        match base64::decode(&remove_newline(reader_buffer.clone())) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Can not decode {}: {}", reader_buffer, e);
+                send_auth_failure(stream.get_mut()).await;
+                return None;
+            }
+        };
+
+    reader_buffer.clear();
+    if let Err(e) = read_handshake_line_async(stream, &mut reader_buffer).await {
+        warn!("Could not read client challenge: {}", e);
+        return None;
+    }
+    let client_challenge = match parse_challenge(&reader_buffer) {
+        Some(c) => c,
+        None => {
+            warn!("Could not get client challenge");
+            return None;
+        }
+    };
+
+    if challenge_response_buf == challenge_response_exp {
+        info!("Client authenticated");
+        let proof = server_proof(
+            identity.get_secret(),
+            client.get_name(),
+            &challenge,
+            &client_challenge,
+            protocol_line,
+        );
+        let verdict = AUTH_SUCCESS.to_string() + &proof_line(&proof);
+        match stream.get_mut().write_all(verdict.as_bytes()).await {
+            Ok(()) => Some((client, challenge, client_challenge)),
+            Err(e) => {
+                warn!("AUTH not sent: {}", e);
+                None
+            }
+        }
+    } else {
+        send_auth_failure(stream.get_mut()).await;
+        None
+    }
+}
+
+async fn send_auth_failure<W: AsyncWrite + Unpin>(stream: &mut W) {
+    info!("Authentication failure. Aborting.");
+    match stream.write_all(AUTH_FAILURE.as_bytes()).await {
+        Ok(()) => info!("NAUTH sent"),
+        Err(e) => warn!("NAUTH not sent: {}", e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfs_client::{Client as _, RfsClientSession};
+    use std::fs;
+    use std::io::{BufRead, BufReader as StdBufReader, Cursor, Write};
+    use std::net::TcpStream as StdTcpStream;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use tempfile::TempDir;
+    use tokio::runtime::Builder;
+
+    /// Returns the configuration of the server `srv1` exporting `root` on `port`, and of its
+    /// client `cli1`.
+    fn config(root: &Path, port: u16) -> RfsConfig {
+        let content = format!(
+            "server:srv1:enl4d3Z1:127.0.0.1:{}:::{}\n\
+             client:cli1:MTIzNDU2\n",
+            port,
+            root.display()
+        );
+        let (conf, errors) = RfsConfig::parse_content(Path::new("rfs_config"), &content);
+        assert!(errors.is_empty());
+        conf
+    }
+
+    /// Start a server on its own runtime, exporting a new temporary directory, and returns the
+    /// directory and the configuration clients use to reach the server.
+    fn start_server() -> (TempDir, RfsConfig) {
+        let root = tempfile::tempdir().unwrap();
+        let server_config = config(root.path(), 1);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let runtime = Builder::new_current_thread().enable_io().build().unwrap();
+            runtime.block_on(async move {
+                let name = String::from("srv1");
+                let socket = String::from("127.0.0.1:0");
+                let server = AsyncRfsServer::bound_to(name, server_config, socket).await.unwrap();
+                sender.send(server.listener.local_addr().unwrap().port()).unwrap();
+                server.listen().await;
+            });
+        });
+        let config = config(root.path(), receiver.recv().unwrap());
+        (root, config)
+    }
+
+    #[test]
+    fn serves_clients_over_loopback() {
+        let (root, config) = start_server();
+        let mut session =
+            RfsClientSession::new(String::from("srv1"), String::from("cli1"), config).unwrap();
+        session.connect().unwrap();
+        let content = b"served by a task".to_vec();
+        session.upload(Cursor::new(content.clone()), "file").unwrap();
+        assert_eq!(fs::read(root.path().join("file")).unwrap(), content);
+        let mut downloaded = Vec::new();
+        session.download("file", &mut downloaded).unwrap();
+        assert_eq!(downloaded, content);
+        session.unlink("file").unwrap();
+        assert!(fs::read_dir(root.path()).unwrap().next().is_none());
+        session.disconnect().unwrap();
+    }
+
+    #[test]
+    fn rejects_undecodable_challenge_responses() {
+        let (_root, config) = start_server();
+        let address = config.get_server_address(String::from("srv1")).unwrap();
+        let mut stream = StdBufReader::new(StdTcpStream::connect(address).unwrap());
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        let (_, choice) = protocol::choose(&line, true).unwrap();
+        stream.get_mut().write_all(choice.as_bytes()).unwrap();
+        for _ in 0..3 {
+            line.clear();
+            stream.read_line(&mut line).unwrap();
+        }
+        assert!(parse_challenge(&line).is_some());
+        stream.get_mut().write_all(b"cli1\nnot base64\n").unwrap();
+        line.clear();
+        stream.read_line(&mut line).unwrap();
+        assert_eq!(line, AUTH_FAILURE);
+    }
+}
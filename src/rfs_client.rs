@@ -1,39 +1,117 @@
 use blowfish::Blowfish;
-use config::{RfsConfig, Config, Field};
-use block_cipher_trait::BlockCipher;
+use crate::config::{RfsConfig, Config, Field, TlsFiles};
 use std::net::TcpStream;
-use std::io::Write;
-use std::io::BufRead;
-use generic_array::GenericArray;
+use std::io::{Read, Write};
+use std::io::{BufRead, BufReader};
 use std::net::Shutdown;
-use base64;
-use rfs_common::{Identity, Named, Challenge, BlowfishKey, get_cipher, get_buf_reader};
+use crate::message::{Message, Reply, Status};
+use crate::message::{WriteFile, WriteFileReply, ReadFile, ReadFileReply, Stat, StatReply, ListDir,
+                     ListDirReply, Mkdir, MkdirReply, Rmdir, RmdirReply, Unlink, UnlinkReply,
+                     Rename, RenameReply, Truncate, TruncateReply, SetTimes, SetTimesReply,
+                     OpenReply, Close, CloseReply, ReadHandle, WriteHandle, StatHandle,
+                     TruncateHandle, FileStat, DirEntry, Timestamp};
+use crate::message_signer::{MessageSigner, DefaultSigner, SignedMessage};
+use crate::frame_cipher::FrameCipher;
+use crate::protocol::{self, Protocol, ProtocolError};
+use crate::rfs_file::{OpenOptions, RfsFile};
+use crate::rfs_common::{Identity, Named, Challenge, SessionId, get_cipher, encrypt_block,
+                        get_buf_reader, generate_challenge, challenge_line, parse_challenge,
+                        check_server_proof, read_handshake_line, Frame, FrameReader, FrameWriter,
+                        CHALLENGE_LENGTH, DEFAULT_CHUNK_LENGTH, MAX_CHUNK_LENGTH};
+use crate::rfs_server::AUTH_SUCCESS;
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsClientStream};
+use std::error::Error;
+use std::fmt;
 use std::io::Error as IoError;
+use std::io::Result as IoResult;
 
 pub struct RfsClientSession {
-    stream: TcpStream,
+    stream: BufReader<Connection>,
     config: RfsConfig,
     identity: Field,
+    server: Field,
     bf: Blowfish,
+    signer: Option<DefaultSigner>,
+    frame_reader: FrameReader,
+    frame_writer: FrameWriter,
+    chunk_length: usize,
+    protocol: Option<Protocol>,
 }
 
 pub trait Client {
-    fn connect(&mut self);
-    fn disconnect(&self) -> Result<(), IoError>;
+    fn connect(&mut self) -> Result<(), RfsClientError>;
+    fn disconnect(&mut self) -> Result<(), IoError>;
+}
+
+/// The connection of a client to its server, either in clear or over TLS.
+enum Connection {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsClientStream>),
+}
+
+impl Connection {
+    fn shutdown(&mut self) -> Result<(), IoError> {
+        match self {
+            &mut Connection::Plain(ref stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(feature = "tls")]
+            &mut Connection::Tls(ref mut stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()?;
+                stream.sock.shutdown(Shutdown::Both)
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        match self {
+            &mut Connection::Plain(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            &mut Connection::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        match self {
+            &mut Connection::Plain(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            &mut Connection::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        match self {
+            &mut Connection::Plain(ref mut stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            &mut Connection::Tls(ref mut stream) => stream.flush(),
+        }
+    }
 }
 
 impl RfsClientSession {
-    pub fn new(serverName: String, clientName: String, config: RfsConfig) -> Option<Self> {
-        match config.clone().get_from_name(clientName) {
+    pub fn new(server_name: String, client_name: String, config: RfsConfig) -> Option<Self> {
+        match config.clone().get_from_name(client_name) {
             Ok(id) => {
-                match config.clone().get_server_address(serverName) {
-                    Some(address) => {
-                        match RfsClientSession::connect_server(address) {
+                let server = config.get_from_name(server_name.clone()).ok().cloned();
+                match (config.clone().get_server_address(server_name), server) {
+                    (Some(address), Some(server)) => {
+                        match RfsClientSession::connect_server(address, &server) {
                             Some(stream) => Some(RfsClientSession {
-                                stream,
+                                stream: get_buf_reader(stream),
                                 config,
                                 identity: id.clone(),
+                                server: server,
                                 bf: get_cipher(id.get_secret()),
+                                signer: None,
+                                frame_reader: FrameReader::new(),
+                                frame_writer: FrameWriter::new(),
+                                chunk_length: DEFAULT_CHUNK_LENGTH,
+                                protocol: None,
                             }),
                             None => {
                                 warn!("Could not create RfsClientSession (Can not connect).");
@@ -41,7 +119,7 @@ impl RfsClientSession {
                             }
                         }
                     }
-                    None => {
+                    _ => {
                         warn!("Could not create RfsClientSession.");
                         None
                     }
@@ -54,83 +132,619 @@ impl RfsClientSession {
         }
     }
 
-    fn send_identity(&mut self) {
+    fn send_identity(&mut self) -> IoResult<()> {
         let name = self.identity.get_name();
-        self.stream.write(name.as_bytes());
-        self.stream.write("\n".as_bytes());
+        self.stream.get_mut().write_all(name.as_bytes())?;
+        self.stream.get_mut().write_all("\n".as_bytes())
     }
 
-    fn connect_server(address: String) -> Option<TcpStream> {
-        match TcpStream::connect(address) {
+    /// Connect to `address`, wrapping the connection in TLS if the config entry of `server`
+    /// requires it.
+    fn connect_server(address: String, server: &Field) -> Option<Connection> {
+        let stream = match TcpStream::connect(address.clone()) {
             Ok(stream) => {
                 info!("Connection successful");
-                Some(stream)
+                stream
             }
             Err(e) => {
                 warn!("Error connecting to server. Reason: {}", e);
+                return None;
+            }
+        };
+        match server.get_tls() {
+            Some(files) => {
+                let host = address.rsplitn(2, ':').last().unwrap_or(&address);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                RfsClientSession::wrap_tls(files, host, stream)
+            }
+            None => Some(Connection::Plain(stream)),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn wrap_tls(files: &TlsFiles, host: &str, stream: TcpStream) -> Option<Connection> {
+        match tls::client_config(files).and_then(|config| tls::connect(&config, host, stream)) {
+            Ok(stream) => {
+                info!("Connection secured with TLS");
+                Some(Connection::Tls(Box::new(stream)))
+            }
+            Err(e) => {
+                warn!("Could not set up TLS. Reason: {}", e);
                 None
             }
         }
     }
 
-    fn authenticate(&mut self) {
-        match get_challenge(self.stream.try_clone().unwrap()) {
-            Some(c) => {
-                info!("Challenge is: {:?}", c);
-                self.send_identity();
-                let c_resp = get_challenge_response(c, self.bf);
-                send_challenge_response(self.stream.try_clone().unwrap(), c_resp);
+    #[cfg(not(feature = "tls"))]
+    fn wrap_tls(_: &TlsFiles, host: &str, _: TcpStream) -> Option<Connection> {
+        warn!("{} requires TLS, but rfs is built without the tls feature", host);
+        None
+    }
+
+    /// Negotiate the protocol with the server, before the authentication.
+    fn negotiate(&mut self) -> Result<(), RfsClientError> {
+        let transport = |e: IoError| {
+            warn!("Protocol negotiation failure. Reason: {}", e);
+            RfsClientError::new(RfsClientErrorKind::Transport)
+        };
+        let mut line = String::new();
+        read_handshake_line(&mut self.stream, &mut line).map_err(transport)?;
+        let (chosen, choice) = protocol::choose(&line, self.config.is_encryption_enabled())?;
+        self.stream.get_mut().write_all(choice.as_bytes()).map_err(transport)?;
+        line.clear();
+        read_handshake_line(&mut self.stream, &mut line).map_err(transport)?;
+        let protocol = protocol::parse_answer(&line, &chosen)?;
+        info!("Protocol negotiated: {:?}", protocol);
+        self.protocol = Some(protocol);
+        Ok(())
+    }
+
+    /// Returns the protocol negotiated with the server, once connected.
+    pub fn get_protocol(&self) -> Option<&Protocol> {
+        self.protocol.as_ref()
+    }
+
+    /// Fails unless `feature` was agreed on with the server.
+    fn require_feature(&self, feature: &str) -> Result<(), RfsClientError> {
+        match self.protocol {
+            Some(ref protocol) if protocol.has_feature(feature) => Ok(()),
+            Some(_) => Err(RfsClientError::from(protocol::missing_feature(feature))),
+            None => Err(RfsClientError::new(RfsClientErrorKind::NotConnected)),
+        }
+    }
+
+    /// Answer the challenge of the server, then check that the server answers ours. The session
+    /// is aborted if the server can not prove that it knows the key of `Field::Server`.
+    fn authenticate(&mut self) -> Result<(), RfsClientError> {
+        if let Err(e) = self.negotiate() {
+            warn!("Could not negotiate the protocol. Reason: {}", e);
+            if let Err(e) = self.disconnect() {
+                warn!("Could not shutdown connection: {}", e);
             }
+            return Err(e);
+        }
+        let c = match get_challenge(&mut self.stream) {
+            Some(c) => c,
             None => {
                 warn!("Could not get challenge");
+                return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+            }
+        };
+        info!("Challenge is: {:?}", c);
+        if let Err(e) = self.send_identity() {
+            warn!("Could not send identity: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        let c_resp = get_challenge_response(c.clone(), &self.bf)?;
+        if let Err(e) = send_challenge_response(self.stream.get_mut(), c_resp) {
+            warn!("Could not send challenge response: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        let client_challenge = generate_challenge();
+        info!("Challenge proposed: {:?}", client_challenge);
+        if let Err(e) = self.stream.get_mut().write_all(
+            challenge_line(&client_challenge).as_bytes(),
+        )
+        {
+            warn!("Could not send challenge: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        let mut verdict = String::new();
+        let mut proof = String::new();
+        if let Err(e) = read_handshake_line(&mut self.stream, &mut verdict) {
+            warn!("Could not read authentication verdict: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        if verdict == AUTH_SUCCESS {
+            if let Err(e) = read_handshake_line(&mut self.stream, &mut proof) {
+                warn!("Could not read server proof: {}", e);
+                return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+            }
+        }
+        info!("Server answered: {}", verdict.trim());
+        let (encryption, protocol_line) = match self.protocol {
+            Some(ref p) => (p.has_feature(protocol::ENCRYPTION), p.get_accepted_line()),
+            None => return Err(RfsClientError::new(RfsClientErrorKind::NotConnected)),
+        };
+        let checked = check_verdict(&self.identity, &self.server, &c, &client_challenge,
+                                    &protocol_line, &verdict, &proof);
+        if checked.is_ok() {
+            if encryption {
+                let secret = self.identity.get_secret();
+                self.frame_writer.set_cipher(
+                    FrameCipher::client_to_server(secret, &c, &client_challenge, &protocol_line),
+                );
+                self.frame_reader.set_cipher(
+                    FrameCipher::server_to_client(secret, &c, &client_challenge, &protocol_line),
+                );
+            }
+            self.signer = Some(DefaultSigner::new(self.identity.get_secret().clone(), c));
+        } else if let Err(e) = self.disconnect() {
+            warn!("Could not shutdown connection: {}", e);
+        }
+        checked
+    }
+
+    /// Returns the number of bytes sent or received by each request of `RfsClientSession::upload`
+    /// and `RfsClientSession::download`.
+    pub fn get_chunk_length(&self) -> usize {
+        self.chunk_length
+    }
+
+    /// Set the number of bytes sent or received by each request of transfers. It is capped to
+    /// `rfs_common::MAX_CHUNK_LENGTH`.
+    pub fn set_chunk_length(&mut self, chunk_length: usize) {
+        self.chunk_length = chunk_length.clamp(1, MAX_CHUNK_LENGTH);
+    }
+
+    /// Open the remote file `path` with `options`. The returned file implements `std::io::Read`,
+    /// `Write` and `Seek`.
+    pub fn open(&mut self, path: &str, options: &OpenOptions) -> IoResult<RfsFile<'_>> {
+        RfsFile::open(self, path, options)
+    }
+
+    /// Write the whole content of `reader` in the remote file `path`, replacing it if it exists.
+    /// The content is sent by chunks, so that it does not have to fit in memory. Returns the
+    /// number of bytes written. Requires the `handles` feature of the protocol.
+    pub fn upload<R: Read>(&mut self, reader: R, path: &str) -> Result<u64, RfsClientError> {
+        self.require_feature(protocol::HANDLES)?;
+        let handle = self.open_handle(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )?;
+        let uploaded = self.upload_to(handle, reader);
+        let closed = self.close_handle(handle);
+        let written = uploaded?;
+        closed.map(|_| written)
+    }
+
+    fn upload_to<R: Read>(&mut self, handle: u64, mut reader: R) -> Result<u64, RfsClientError> {
+        let mut position = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(self.chunk_length);
+            (&mut reader)
+                .take(self.chunk_length as u64)
+                .read_to_end(&mut chunk)
+                .map_err(|e| RfsClientError::new(RfsClientErrorKind::Io(e)))?;
+            if chunk.is_empty() {
+                return Ok(position);
+            }
+            let length = chunk.len() as u64;
+            let written = self.write_handle(handle, position, chunk)?;
+            position += written;
+            if written < length {
+                return Err(RfsClientError::new(RfsClientErrorKind::ShortWrite(written)));
+            }
+        }
+    }
+
+    /// Write the whole content of the remote file `path` in `writer`. The content is received by
+    /// chunks, so that it does not have to fit in memory. Returns the number of bytes read.
+    /// Requires the `handles` feature of the protocol.
+    pub fn download<W: Write>(&mut self, path: &str, writer: W) -> Result<u64, RfsClientError> {
+        self.require_feature(protocol::HANDLES)?;
+        let handle = self.open_handle(path, OpenOptions::new().read(true))?;
+        let downloaded = self.download_from(handle, writer);
+        let closed = self.close_handle(handle);
+        let read = downloaded?;
+        closed.map(|_| read)
+    }
+
+    fn download_from<W: Write>(
+        &mut self,
+        handle: u64,
+        mut writer: W,
+    ) -> Result<u64, RfsClientError> {
+        let mut position = 0;
+        loop {
+            let (data, eof) = self.read_handle(handle, position, self.chunk_length as u64)?;
+            writer
+                .write_all(&data)
+                .map_err(|e| RfsClientError::new(RfsClientErrorKind::Io(e)))?;
+            position += data.len() as u64;
+            if eof || data.is_empty() {
+                return Ok(position);
+            }
+        }
+    }
+
+    /// Open the remote file `path` with `options`. Returns the handle identifying the open file
+    /// in the requests of the session, until it is closed with `RfsClientSession::close_handle`.
+    /// The file stays open if it is renamed or removed. Requires the `handles` feature of the
+    /// protocol.
+    pub fn open_handle(
+        &mut self,
+        path: &str,
+        options: &OpenOptions,
+    ) -> Result<u64, RfsClientError> {
+        self.require_feature(protocol::HANDLES)?;
+        let reply: OpenReply = self.request(options.to_message(path))?;
+        Ok(reply.get_handle())
+    }
+
+    /// Close the remote file opened as `handle`.
+    pub fn close_handle(&mut self, handle: u64) -> Result<(), RfsClientError> {
+        let _: CloseReply = self.request(Close::new(handle))?;
+        Ok(())
+    }
+
+    /// Read at most `length` bytes of the remote file opened as `handle`, starting at `position`.
+    /// Returns the data read, and whether the end of the file has been reached.
+    pub fn read_handle(
+        &mut self,
+        handle: u64,
+        position: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), RfsClientError> {
+        let reply: ReadFileReply = self.request(ReadHandle::new(handle, position, length))?;
+        let eof = reply.is_eof();
+        Ok((reply.into_data(), eof))
+    }
+
+    /// Write `content` at `position` in the remote file opened as `handle`. Returns the number of
+    /// bytes written by the server.
+    pub fn write_handle(
+        &mut self,
+        handle: u64,
+        position: u64,
+        content: Vec<u8>,
+    ) -> Result<u64, RfsClientError> {
+        let reply: WriteFileReply = self.request(WriteHandle::new(handle, position, content))?;
+        Ok(reply.get_written())
+    }
+
+    /// Returns the metadata of the remote file opened as `handle`.
+    pub fn stat_handle(&mut self, handle: u64) -> Result<FileStat, RfsClientError> {
+        let reply: StatReply = self.request(StatHandle::new(handle))?;
+        reply.into_stat().ok_or(
+            RfsClientError::new(RfsClientErrorKind::Serialization),
+        )
+    }
+
+    /// Truncate (or extend with zeros) the remote file opened as `handle` to `length` bytes.
+    pub fn truncate_handle(&mut self, handle: u64, length: u64) -> Result<(), RfsClientError> {
+        let _: TruncateReply = self.request(TruncateHandle::new(handle, length))?;
+        Ok(())
+    }
+
+    /// Write `content` at `position` in the remote file `filename`. Returns the number of bytes
+    /// written by the server.
+    pub fn write_at(
+        &mut self,
+        filename: &str,
+        position: u64,
+        content: Vec<u8>,
+    ) -> Result<u64, RfsClientError> {
+        let reply: WriteFileReply = self.request(WriteFile::new(content, position, filename))?;
+        Ok(reply.get_written())
+    }
+
+    /// Read at most `length` bytes of the remote file `filename`, starting at `position`. Returns
+    /// the data read, and whether the end of the file has been reached.
+    pub fn read_at(
+        &mut self,
+        filename: &str,
+        position: u64,
+        length: u64,
+    ) -> Result<(Vec<u8>, bool), RfsClientError> {
+        let reply: ReadFileReply = self.request(ReadFile::new(filename, position, length))?;
+        let eof = reply.is_eof();
+        Ok((reply.into_data(), eof))
+    }
+
+    /// Returns the metadata of the remote file `path`.
+    pub fn stat(&mut self, path: &str) -> Result<FileStat, RfsClientError> {
+        let reply: StatReply = self.request(Stat::new(path))?;
+        reply.into_stat().ok_or(
+            RfsClientError::new(RfsClientErrorKind::Serialization),
+        )
+    }
+
+    /// Returns the entries of the remote directory `path`.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, RfsClientError> {
+        let reply: ListDirReply = self.request(ListDir::new(path))?;
+        Ok(reply.into_entries())
+    }
+
+    /// Create the remote directory `path`.
+    pub fn mkdir(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: MkdirReply = self.request(Mkdir::new(path))?;
+        Ok(())
+    }
+
+    /// Remove the empty remote directory `path`.
+    pub fn rmdir(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: RmdirReply = self.request(Rmdir::new(path))?;
+        Ok(())
+    }
+
+    /// Remove the remote file `path`.
+    pub fn unlink(&mut self, path: &str) -> Result<(), RfsClientError> {
+        let _: UnlinkReply = self.request(Unlink::new(path))?;
+        Ok(())
+    }
+
+    /// Rename the remote file `from` into `to`.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), RfsClientError> {
+        let _: RenameReply = self.request(Rename::new(from, to))?;
+        Ok(())
+    }
+
+    /// Truncate (or extend with zeros) the remote file `path` to `length` bytes.
+    pub fn truncate(&mut self, path: &str, length: u64) -> Result<(), RfsClientError> {
+        let _: TruncateReply = self.request(Truncate::new(path, length))?;
+        Ok(())
+    }
+
+    /// Set the access and modification times of the remote file `path`.
+    pub fn set_times(
+        &mut self,
+        path: &str,
+        accessed: Timestamp,
+        modified: Timestamp,
+    ) -> Result<(), RfsClientError> {
+        let _: SetTimesReply = self.request(SetTimes::new(path, accessed, modified))?;
+        Ok(())
+    }
+
+    /// Sign and send a request, then wait for the signed reply of the server. Replies with a
+    /// status other than `Status::Ok` are turned into errors.
+    fn request<M: Message, R: Reply>(&mut self, message: M) -> Result<R, RfsClientError> {
+        let signer = self.signer.as_mut().ok_or(RfsClientError::new(
+            RfsClientErrorKind::NotConnected,
+        ))?;
+        let frame = request_frame(signer, message)?;
+        if let Err(e) = self.frame_writer.write_frame(self.stream.get_mut(), frame) {
+            warn!("Could not send request. Reason: {}", e);
+            return Err(RfsClientError::new(RfsClientErrorKind::Transport));
+        }
+        match self.frame_reader.read_frame(&mut self.stream) {
+            Ok(frame) => reply_of_frame(signer, frame),
+            Err(e) => {
+                warn!("Could not receive reply. Reason: {}", e);
+                Err(RfsClientError::new(RfsClientErrorKind::Transport))
             }
         }
     }
 }
 
+/// Sign `message`, and returns the frame carrying it.
+pub(crate) fn request_frame<S, M>(signer: &mut S, message: M) -> Result<Frame, RfsClientError>
+where
+    S: MessageSigner,
+    M: Message,
+{
+    match signer.sign(message) {
+        Some(signed_message) => Ok(signed_message.into_frame()),
+        None => Err(RfsClientError::new(RfsClientErrorKind::Serialization)),
+    }
+}
+
+/// Check the signature of the reply carried by `frame`, and deserialize it. Replies with a status
+/// other than `Status::Ok` are turned into errors.
+pub(crate) fn reply_of_frame<S, R>(signer: &mut S, frame: Frame) -> Result<R, RfsClientError>
+where
+    S: MessageSigner,
+    R: Reply,
+{
+    let signed_reply = SignedMessage::from_frame(frame).ok_or(RfsClientError::new(
+        RfsClientErrorKind::Serialization,
+    ))?;
+    if let Err(e) = signer.assert(&signed_reply) {
+        warn!("Invalid reply from server. Reason: {}", e);
+        return Err(RfsClientError::new(RfsClientErrorKind::BadSignature));
+    }
+    if signed_reply.get_message_type() != R::message_type() {
+        warn!(
+            "Unexpected reply from server: {:?}",
+            signed_reply.get_message_type()
+        );
+        return Err(RfsClientError::new(RfsClientErrorKind::Serialization));
+    }
+    let reply = R::deserialize(signed_reply.get_message()).ok_or(
+        RfsClientError::new(
+            RfsClientErrorKind::Serialization,
+        ),
+    )?;
+    match reply.get_status() {
+        Status::Ok => Ok(reply),
+        status => Err(RfsClientError::new(RfsClientErrorKind::Remote(status))),
+    }
+}
+
 impl Client for RfsClientSession {
-    fn connect(&mut self) {
+    fn connect(&mut self) -> Result<(), RfsClientError> {
         self.authenticate()
     }
 
-    fn disconnect(&self) -> Result<(), IoError> {
+    fn disconnect(&mut self) -> Result<(), IoError> {
         info!("Shutdown connection");
-        self.stream.shutdown(Shutdown::Both)
+        self.stream.get_mut().shutdown()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum RfsClientErrorKind {
+    NotConnected,
+    Transport,
+    Serialization,
+    BadSignature,
+    Rejected,
+    ServerNotAuthenticated,
+    Remote(Status),
+    Io(IoError),
+    ShortWrite(u64),
+    Protocol(ProtocolError),
+}
+
+/// The error type for requests sent by a `RfsClientSession`.
+#[derive(Debug)]
+pub struct RfsClientError {
+    kind: RfsClientErrorKind,
+}
+
+impl RfsClientError {
+    pub(crate) fn new(kind: RfsClientErrorKind) -> Self {
+        RfsClientError { kind: kind }
+    }
+
+    /// Returns the status sent by the server, if the request was rejected by the server.
+    pub fn get_status(&self) -> Option<Status> {
+        match self.kind {
+            RfsClientErrorKind::Remote(status) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProtocolError> for RfsClientError {
+    fn from(e: ProtocolError) -> Self {
+        RfsClientError::new(RfsClientErrorKind::Protocol(e))
     }
 }
 
-fn get_challenge(stream: TcpStream) -> Option<Challenge> {
-    let mut buf = get_buf_reader(stream);
+impl From<RfsClientError> for IoError {
+    fn from(e: RfsClientError) -> Self {
+        match e.kind {
+            RfsClientErrorKind::Io(e) => e,
+            RfsClientErrorKind::Remote(status) => IoError::new(status.io_error_kind(), e),
+            _ => IoError::other(e),
+        }
+    }
+}
+
+impl fmt::Display for RfsClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            RfsClientErrorKind::NotConnected => {
+                write!(f, "RfsClient Error: the session is not authenticated")
+            }
+            RfsClientErrorKind::Transport => {
+                write!(f, "RfsClient Error: could not exchange messages with the server")
+            }
+            RfsClientErrorKind::Serialization => {
+                write!(f, "RfsClient Error: could not (de)serialize a message")
+            }
+            RfsClientErrorKind::BadSignature => {
+                write!(f, "RfsClient Error: the reply of the server is not correctly signed")
+            }
+            RfsClientErrorKind::Rejected => {
+                write!(f, "RfsClient Error: the server rejected the authentication of the client")
+            }
+            RfsClientErrorKind::ServerNotAuthenticated => {
+                write!(f, "RfsClient Error: the server could not prove that it knows its key")
+            }
+            RfsClientErrorKind::Remote(status) => {
+                write!(f, "RfsClient Error: the server replied {:?}", status)
+            }
+            RfsClientErrorKind::Io(ref e) => write!(f, "RfsClient Error: {}", e),
+            RfsClientErrorKind::ShortWrite(written) => {
+                write!(f, "RfsClient Error: the server wrote only {} bytes of a chunk", written)
+            }
+            RfsClientErrorKind::Protocol(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RfsClientError {
+    fn description(&self) -> &str {
+        match self.kind {
+            RfsClientErrorKind::NotConnected => "The session is not authenticated",
+            RfsClientErrorKind::Transport => "Could not exchange messages with the server",
+            RfsClientErrorKind::Serialization => "Could not (de)serialize a message",
+            RfsClientErrorKind::BadSignature => "The reply of the server is not correctly signed",
+            RfsClientErrorKind::Rejected => "The server rejected the authentication of the client",
+            RfsClientErrorKind::ServerNotAuthenticated => {
+                "The server could not prove that it knows its key"
+            }
+            RfsClientErrorKind::Remote(_) => "The server could not perform the request",
+            RfsClientErrorKind::Io(_) => "Could not read or write the local data of a transfer",
+            RfsClientErrorKind::ShortWrite(_) => "The server did not write the whole content",
+            RfsClientErrorKind::Protocol(_) => {
+                "The client and the server could not agree on a protocol"
+            }
+        }
+    }
+}
+
+fn get_challenge<R: BufRead>(buf: &mut R) -> Option<Challenge> {
     let mut challenge_line = String::new();
-    buf.read_line(&mut challenge_line);
+    read_handshake_line(buf, &mut challenge_line).ok()?;
     let mut challenge_line = String::new();
-    buf.read_line(&mut challenge_line);
-    let mut challenge_split = challenge_line.split('\"');
-    challenge_split.next().unwrap();
-    let splitted_line_2 = challenge_split.next().unwrap();
-    let challenge = base64::decode(splitted_line_2);
-    match challenge {
-        Ok(v) => {
-            info!("Challenge found: {:?}", v);
-            Some(v)
+    read_handshake_line(buf, &mut challenge_line).ok()?;
+    parse_challenge(&challenge_line)
+}
+
+/// Check the answer of the server to the authentication of the client: `verdict` must accept the
+/// client, and `proof_line` must prove that the server knows the key of `server`, and that it
+/// accepted the protocol with `protocol_line`.
+pub(crate) fn check_verdict(
+    client: &Field,
+    server: &Field,
+    session_id: &SessionId,
+    client_challenge: &Challenge,
+    protocol_line: &str,
+    verdict: &str,
+    proof_line: &str,
+) -> Result<(), RfsClientError> {
+    if verdict != AUTH_SUCCESS {
+        warn!("Server rejected the authentication");
+        return Err(RfsClientError::new(RfsClientErrorKind::Rejected));
+    }
+    match parse_challenge(proof_line) {
+        Some(ref proof) if check_server_proof(
+            server.get_secret(),
+            client.get_name(),
+            session_id,
+            client_challenge,
+            protocol_line,
+            proof,
+        ) => {
+            info!("Server {} authenticated", server.get_name());
+            Ok(())
         }
-        Err(e) => {
-            warn!("Can not decode {}: {}", splitted_line_2, e);
-            None
+        _ => {
+            warn!("Server {} could not prove its identity. Aborting.", server.get_name());
+            Err(RfsClientError::new(RfsClientErrorKind::ServerNotAuthenticated))
         }
     }
-
 }
 
-fn send_challenge_response(mut stream: TcpStream, c: Challenge) {
+fn send_challenge_response<W: Write>(stream: &mut W, c: Challenge) -> IoResult<()> {
     info!("Sending challenge: {:?}", c);
-    stream.write(base64::encode(&c).as_bytes());
-    stream.write("\n".as_bytes());
+    stream.write_all(base64::encode(&c).as_bytes())?;
+    stream.write_all("\n".as_bytes())
 }
 
-fn get_challenge_response(c: Challenge, b: Blowfish) -> Challenge {
-    let mut out_buf = GenericArray::new();
-    let in_buf = GenericArray::from_slice(&c);
-    b.encrypt_block(&in_buf, &mut out_buf);
-    out_buf.to_vec()
+/// Returns the answer to the challenge `c` of the server, encrypted with `b`. Fails if the
+/// challenge is not a single block.
+pub(crate) fn get_challenge_response(
+    c: Challenge,
+    b: &Blowfish,
+) -> Result<Challenge, RfsClientError> {
+    if c.len() != CHALLENGE_LENGTH {
+        warn!("Challenge of {} bytes, expected {}", c.len(), CHALLENGE_LENGTH);
+        return Err(RfsClientError::from(protocol::bad_challenge(c.len())));
+    }
+    Ok(encrypt_block(b, &c))
 }
@@ -1,19 +1,47 @@
 //! This module contains common definitions to rfs_client and rfs_server.
 
 use blowfish::Blowfish;
-use block_cipher_trait::BlockCipherVarKey;
-use std::io::BufReader;
-use std::io::Read;
-use config::Field;
+use blowfish::cipher::{self, BlockEncrypt};
+use blowfish::cipher::generic_array::GenericArray;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::io::{Read, Write};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use crate::config::Field;
+use crate::frame_cipher::{FrameCipher, TAG_LENGTH};
+use crate::message::MessageType;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// BlowfishKey type.
 pub type BlowfishKey = Vec<u8>;
+/// Length of the shortest key accepted by Blowfish, in bytes.
+pub const MIN_KEY_LENGTH: usize = 4;
+/// Length of the longest key accepted by Blowfish, in bytes.
+pub const MAX_KEY_LENGTH: usize = 56;
 /// Challenge type.
 pub type Challenge = Vec<u8>;
+/// Length of the challenges sent by servers, in bytes: a single Blowfish block.
+pub const CHALLENGE_LENGTH: usize = 8;
+/// Session identifier type. The challenge of the handshake, which is freshly generated by the
+/// server for each connection, identifies the session.
+pub type SessionId = Vec<u8>;
 
-/// Returns a cipher given a key.
+/// Returns a cipher given a key. The key must be between `MIN_KEY_LENGTH` and `MAX_KEY_LENGTH`
+/// bytes long, which is checked when reading the configuration.
 pub fn get_cipher(key: &BlowfishKey) -> Blowfish {
-    Blowfish::new(key.as_slice())
+    <Blowfish as cipher::KeyInit>::new_from_slice(key.as_slice())
+        .expect("Blowfish key of invalid length")
+}
+
+/// Returns `block`, which must be `CHALLENGE_LENGTH` bytes long, encrypted with `cipher`.
+pub fn encrypt_block(cipher: &Blowfish, block: &[u8]) -> Vec<u8> {
+    let mut buf = GenericArray::clone_from_slice(block);
+    cipher.encrypt_block(&mut buf);
+    buf.to_vec()
 }
 
 /// Returns a BufReader given a TcpStream.
@@ -21,6 +49,417 @@ pub fn get_buf_reader<R: Read>(stream: R) -> BufReader<R> {
     BufReader::new(stream)
 }
 
+/// Longest line of the handshake, in bytes, including its newline. The handshake happens before
+/// authentication, so the lines sent by the peer must be bounded.
+pub const MAX_LINE_LENGTH: u64 = 1024;
+
+/// Read a line of the handshake into `line`. Fails if the line is longer than `MAX_LINE_LENGTH`.
+pub fn read_handshake_line<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+) -> Result<usize, IoError> {
+    let n = Read::take(reader, MAX_LINE_LENGTH).read_line(line)?;
+    check_line_length(n, line)
+}
+
+/// Asynchronous counterpart of `read_handshake_line`.
+#[cfg(feature = "async")]
+pub async fn read_handshake_line_async<R>(
+    reader: &mut R,
+    line: &mut String,
+) -> Result<usize, IoError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let n = AsyncReadExt::take(reader, MAX_LINE_LENGTH).read_line(line).await?;
+    check_line_length(n, line)
+}
+
+fn check_line_length(n: usize, line: &str) -> Result<usize, IoError> {
+    if n as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(IoError::new(IoErrorKind::InvalidData, "Handshake line too long"));
+    }
+    Ok(n)
+}
+
+/// Returns a fresh random challenge.
+pub fn generate_challenge() -> Challenge {
+    use rand::os::OsRng;
+    use rand::Rng;
+
+    let mut rng = OsRng::new().unwrap();
+    let mut buf: [u8; CHALLENGE_LENGTH] = [0; CHALLENGE_LENGTH];
+    rng.fill_bytes(&mut buf);
+    buf.to_vec()
+}
+
+/// Returns a fresh random key of `length` bytes, drawn from the random number generator of the
+/// operating system.
+pub fn generate_key(length: usize) -> Result<BlowfishKey, IoError> {
+    use rand::os::OsRng;
+    use rand::Rng;
+
+    let mut rng = OsRng::new()?;
+    let mut key = vec![0; length];
+    rng.fill_bytes(&mut key);
+    Ok(key)
+}
+
+/// Format a challenge on a line of the handshake, as `Challenge is: "..."`.
+pub fn challenge_line(challenge: &Challenge) -> String {
+    "Challenge is: \"".to_string() + &base64::encode(challenge) + "\"\n"
+}
+
+/// Extract the base64 encoded value quoted in a line of the handshake, such as the one built by
+/// `challenge_line`.
+pub fn parse_challenge(challenge_line: &str) -> Option<Challenge> {
+    let splitted_line_2 = match challenge_line.split('\"').nth(1) {
+        Some(c) => c,
+        None => {
+            warn!("No challenge in {}", challenge_line);
+            return None;
+        }
+    };
+    let challenge = base64::decode(splitted_line_2);
+    match challenge {
+        Ok(v) => {
+            info!("Challenge found: {:?}", v);
+            Some(v)
+        }
+        Err(e) => {
+            warn!("Can not decode {}: {}", splitted_line_2, e);
+            None
+        }
+    }
+
+}
+
+fn server_proof_mac(
+    server_key: &BlowfishKey,
+    client_name: &str,
+    session_id: &SessionId,
+    client_challenge: &Challenge,
+    protocol_line: &str,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(server_key).expect(
+        "HMAC accepts keys of any length",
+    );
+    mac.update(client_name.as_bytes());
+    mac.update(&[0]);
+    mac.update(session_id);
+    mac.update(client_challenge);
+    mac.update(protocol_line.as_bytes());
+    mac
+}
+
+/// Returns the proof that the server knows `server_key`: an HMAC-SHA256 of the challenge sent by
+/// the client. It also covers the name of the client and the session, so that the proof obtained
+/// by a client can not be relayed to another one, and `protocol_line`, the line accepting the
+/// protocol (see `Protocol::get_accepted_line`), so that a client whose negotiation was altered
+/// on the way rejects the server.
+pub fn server_proof(
+    server_key: &BlowfishKey,
+    client_name: &str,
+    session_id: &SessionId,
+    client_challenge: &Challenge,
+    protocol_line: &str,
+) -> Vec<u8> {
+    server_proof_mac(server_key, client_name, session_id, client_challenge, protocol_line)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+/// Check a proof built by `server_proof`.
+pub fn check_server_proof(
+    server_key: &BlowfishKey,
+    client_name: &str,
+    session_id: &SessionId,
+    client_challenge: &Challenge,
+    protocol_line: &str,
+    proof: &[u8],
+) -> bool {
+    server_proof_mac(server_key, client_name, session_id, client_challenge, protocol_line)
+        .verify_slice(proof)
+        .is_ok()
+}
+
+/// Version of the frame format, sent in every frame.
+pub const FRAME_VERSION: u8 = 1;
+/// Length of a frame header: payload length (4 bytes, big endian), version and message type.
+pub const FRAME_HEADER_LENGTH: usize = 6;
+/// Maximal length of a frame payload. Longer frames are rejected before being read.
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+/// Largest number of bytes of a file sent or received by a single request. Servers return at most
+/// that many bytes to a `ReadFile` request. Larger transfers are split into chunks.
+pub const MAX_CHUNK_LENGTH: usize = 4 * 1024 * 1024;
+/// Number of bytes of a file sent or received by a single request, unless configured otherwise.
+pub const DEFAULT_CHUNK_LENGTH: usize = 256 * 1024;
+
+/// A frame, i.e. the unit of data exchanged after authentication. It carries a typed payload,
+/// typically a `message_signer::SignedMessage`.
+#[derive(Debug)]
+pub struct Frame {
+    message_type: MessageType,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a frame carrying `payload`, which contains a message of type `message_type`.
+    pub fn new(message_type: MessageType, payload: Vec<u8>) -> Self {
+        Frame {
+            message_type: message_type,
+            payload: payload,
+        }
+    }
+
+    /// Returns the type of the message carried by the frame.
+    pub fn get_message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    /// Consumes the frame, returning its payload.
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+}
+
+/// Reads frames from a stream.
+pub struct FrameReader {
+    cipher: Option<FrameCipher>,
+}
+
+impl FrameReader {
+    /// Create a new `FrameReader`, reading frames in clear.
+    pub fn new() -> Self {
+        FrameReader { cipher: None }
+    }
+
+    /// Decrypt the frames read from now on with `cipher`.
+    pub fn set_cipher(&mut self, cipher: FrameCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    /// Read the next frame on `stream`.
+    pub fn read_frame<R: Read>(&mut self, stream: &mut R) -> Result<Frame, FrameError> {
+        let mut header = [0; FRAME_HEADER_LENGTH];
+        stream.read_exact(&mut header).map_err(FrameError::from_io)?;
+        let (message_type, length) = self.decode_header(&header)?;
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).map_err(FrameError::from_io)?;
+        self.decode(message_type, payload)
+    }
+
+    /// Read the next frame on the asynchronous `stream`.
+    #[cfg(feature = "async")]
+    pub async fn read_frame_async<R>(&mut self, stream: &mut R) -> Result<Frame, FrameError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut header = [0; FRAME_HEADER_LENGTH];
+        stream.read_exact(&mut header).await.map_err(
+            FrameError::from_io,
+        )?;
+        let (message_type, length) = self.decode_header(&header)?;
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).await.map_err(
+            FrameError::from_io,
+        )?;
+        self.decode(message_type, payload)
+    }
+
+    /// Decode a frame header, returning the type of the message and the length of the payload
+    /// which follows.
+    pub fn decode_header(
+        &self,
+        header: &[u8; FRAME_HEADER_LENGTH],
+    ) -> Result<(MessageType, usize), FrameError> {
+        let length = ((header[0] as usize) << 24) | ((header[1] as usize) << 16) |
+            ((header[2] as usize) << 8) | (header[3] as usize);
+        if header[4] != FRAME_VERSION {
+            return Err(FrameError::new(FrameErrorKind::UnsupportedVersion(header[4])));
+        }
+        let overhead = if self.cipher.is_some() { TAG_LENGTH } else { 0 };
+        if length > MAX_FRAME_LENGTH + overhead {
+            return Err(FrameError::new(FrameErrorKind::TooLong(length)));
+        }
+        match MessageType::from_tag(header[5]) {
+            Some(message_type) => Ok((message_type, length)),
+            None => Err(FrameError::new(FrameErrorKind::UnknownMessageType(header[5]))),
+        }
+    }
+
+    /// Build the frame from its decoded header and its payload, decrypting the payload if a
+    /// cipher is set.
+    pub fn decode(
+        &mut self,
+        message_type: MessageType,
+        payload: Vec<u8>,
+    ) -> Result<Frame, FrameError> {
+        match self.cipher {
+            Some(ref mut cipher) => {
+                match cipher.open(&[FRAME_VERSION, message_type.tag()], &payload) {
+                    Some(payload) => Ok(Frame::new(message_type, payload)),
+                    None => Err(FrameError::new(FrameErrorKind::Decryption)),
+                }
+            }
+            None => Ok(Frame::new(message_type, payload)),
+        }
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        FrameReader::new()
+    }
+}
+
+/// Writes frames on a stream.
+pub struct FrameWriter {
+    cipher: Option<FrameCipher>,
+}
+
+impl FrameWriter {
+    /// Create a new `FrameWriter`, writing frames in clear.
+    pub fn new() -> Self {
+        FrameWriter { cipher: None }
+    }
+
+    /// Encrypt the frames written from now on with `cipher`.
+    pub fn set_cipher(&mut self, cipher: FrameCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    /// Write `frame` on `stream`.
+    pub fn write_frame<W: Write>(
+        &mut self,
+        stream: &mut W,
+        frame: Frame,
+    ) -> Result<(), FrameError> {
+        let bytes = self.encode(frame)?;
+        stream.write_all(&bytes).map_err(FrameError::from_io)?;
+        stream.flush().map_err(FrameError::from_io)
+    }
+
+    /// Write `frame` on the asynchronous `stream`.
+    #[cfg(feature = "async")]
+    pub async fn write_frame_async<W>(
+        &mut self,
+        stream: &mut W,
+        frame: Frame,
+    ) -> Result<(), FrameError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let bytes = self.encode(frame)?;
+        stream.write_all(&bytes).await.map_err(FrameError::from_io)?;
+        stream.flush().await.map_err(FrameError::from_io)
+    }
+
+    /// Encode `frame` (header and payload) into bytes, ready to be sent. The payload is encrypted
+    /// if a cipher is set.
+    pub fn encode(&mut self, frame: Frame) -> Result<Vec<u8>, FrameError> {
+        if frame.payload.len() > MAX_FRAME_LENGTH {
+            return Err(FrameError::new(FrameErrorKind::TooLong(frame.payload.len())));
+        }
+        let payload = match self.cipher {
+            Some(ref mut cipher) => {
+                cipher
+                    .seal(&[FRAME_VERSION, frame.message_type.tag()], &frame.payload)
+                    .ok_or(FrameError::new(FrameErrorKind::Encryption))?
+            }
+            None => frame.payload,
+        };
+        let length = payload.len();
+        let mut bytes = Vec::with_capacity(FRAME_HEADER_LENGTH + length);
+        bytes.push((length >> 24) as u8);
+        bytes.push((length >> 16) as u8);
+        bytes.push((length >> 8) as u8);
+        bytes.push(length as u8);
+        bytes.push(FRAME_VERSION);
+        bytes.push(frame.message_type.tag());
+        bytes.extend(payload);
+        Ok(bytes)
+    }
+}
+
+impl Default for FrameWriter {
+    fn default() -> Self {
+        FrameWriter::new()
+    }
+}
+
+#[derive(Debug)]
+enum FrameErrorKind {
+    Closed,
+    Io(IoError),
+    UnsupportedVersion(u8),
+    UnknownMessageType(u8),
+    TooLong(usize),
+    Encryption,
+    Decryption,
+}
+
+/// The error type for frame reading and writing.
+#[derive(Debug)]
+pub struct FrameError {
+    kind: FrameErrorKind,
+}
+
+impl FrameError {
+    fn new(kind: FrameErrorKind) -> Self {
+        FrameError { kind: kind }
+    }
+
+    fn from_io(e: IoError) -> Self {
+        match e.kind() {
+            IoErrorKind::UnexpectedEof => FrameError::new(FrameErrorKind::Closed),
+            _ => FrameError::new(FrameErrorKind::Io(e)),
+        }
+    }
+
+    /// Returns `true` if the error is due to the stream being closed by the peer.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.kind, FrameErrorKind::Closed)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FrameErrorKind::Closed => write!(f, "Frame Error: the stream has been closed"),
+            FrameErrorKind::Io(ref e) => write!(f, "Frame Error: {}", e),
+            FrameErrorKind::UnsupportedVersion(version) => {
+                write!(f, "Frame Error: unsupported frame version {}", version)
+            }
+            FrameErrorKind::UnknownMessageType(tag) => {
+                write!(f, "Frame Error: unknown message type {}", tag)
+            }
+            FrameErrorKind::TooLong(length) => {
+                write!(f, "Frame Error: frame of {} bytes, at most {}", length, MAX_FRAME_LENGTH)
+            }
+            FrameErrorKind::Encryption => write!(f, "Frame Error: could not encrypt the frame"),
+            FrameErrorKind::Decryption => {
+                write!(f, "Frame Error: could not decrypt or authenticate the frame")
+            }
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn description(&self) -> &str {
+        match self.kind {
+            FrameErrorKind::Closed => "The stream has been closed",
+            FrameErrorKind::Io(_) => "Could not read or write on the stream",
+            FrameErrorKind::UnsupportedVersion(_) => "The frame format version is not supported",
+            FrameErrorKind::UnknownMessageType(_) => "The frame carries an unknown message type",
+            FrameErrorKind::TooLong(_) => "The frame is too long",
+            FrameErrorKind::Encryption => "The frame could not be encrypted",
+            FrameErrorKind::Decryption => "The frame could not be decrypted or authenticated",
+        }
+    }
+}
+
 /// Identity trait. Encapsulate a name and a secret.
 pub trait Identity: Clone + Named<Name = String> + Sized {
     fn get_secret(&self) -> &BlowfishKey;
@@ -66,7 +505,173 @@ pub fn welcome(s: Field) {
             key: _,
             address,
             port,
-        } => info!("Welcome on server {} at {}", name, address + ":" + &port),
-        Field::Client { name, key: _ } => info!("Welcome on client {}", name),
+            tls,
+            root: _,
+        } => {
+            info!(
+                "Welcome on server {} at {}{}",
+                name,
+                address + ":" + &port,
+                if tls.is_some() { " (TLS)" } else { "" }
+            )
+        }
+        Field::Client {
+            name,
+            key: _,
+            root: _,
+            permissions: _,
+        } => info!("Welcome on client {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header(length: usize, version: u8, tag: u8) -> [u8; FRAME_HEADER_LENGTH] {
+        let length = (length as u32).to_be_bytes();
+        [length[0], length[1], length[2], length[3], version, tag]
+    }
+
+    fn cipher(client_to_server: bool) -> FrameCipher {
+        let (secret, session, challenge) = (b"secret key".to_vec(), vec![1; 8], vec![2; 8]);
+        let protocol_line = "Protocol accepted: 1; features: encryption";
+        if client_to_server {
+            FrameCipher::client_to_server(&secret, &session, &challenge, protocol_line)
+        } else {
+            FrameCipher::server_to_client(&secret, &session, &challenge, protocol_line)
+        }
+    }
+
+    #[test]
+    fn blowfish_matches_reference_vector() {
+        let cipher = get_cipher(&vec![0; 8]);
+        let expected = [0x4e, 0xf9, 0x97, 0x45, 0x61, 0x98, 0xdd, 0x78];
+        assert_eq!(encrypt_block(&cipher, &[0; CHALLENGE_LENGTH]), expected);
+    }
+
+    #[test]
+    fn writes_and_reads_frames() {
+        let mut writer = FrameWriter::new();
+        let mut stream = Vec::new();
+        writer.write_frame(&mut stream, Frame::new(MessageType::Stat, b"one".to_vec())).unwrap();
+        writer.write_frame(&mut stream, Frame::new(MessageType::ListDir, Vec::new())).unwrap();
+        let tag = MessageType::Stat.tag();
+        assert_eq!(stream[..FRAME_HEADER_LENGTH], header(3, FRAME_VERSION, tag));
+
+        let mut reader = FrameReader::new();
+        let mut stream = Cursor::new(stream);
+        let frame = reader.read_frame(&mut stream).unwrap();
+        assert_eq!(frame.get_message_type(), MessageType::Stat);
+        assert_eq!(frame.into_payload(), b"one");
+        let frame = reader.read_frame(&mut stream).unwrap();
+        assert_eq!(frame.get_message_type(), MessageType::ListDir);
+        assert!(frame.into_payload().is_empty());
+        assert!(reader.read_frame(&mut stream).unwrap_err().is_closed());
+    }
+
+    #[test]
+    fn encrypts_frames() {
+        let mut writer = FrameWriter::new();
+        writer.set_cipher(cipher(true));
+        let bytes = writer.encode(Frame::new(MessageType::Stat, b"secret".to_vec())).unwrap();
+        assert_eq!(bytes.len(), FRAME_HEADER_LENGTH + 6 + TAG_LENGTH);
+        assert!(!bytes.windows(6).any(|w| w == b"secret"));
+
+        let mut reader = FrameReader::new();
+        reader.set_cipher(cipher(false));
+        assert!(reader.read_frame(&mut Cursor::new(bytes.clone())).is_err());
+        let mut reader = FrameReader::new();
+        reader.set_cipher(cipher(true));
+        let frame = reader.read_frame(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(frame.into_payload(), b"secret");
+    }
+
+    #[test]
+    fn bounds_frame_lengths() {
+        let tag = MessageType::Stat.tag();
+        let mut reader = FrameReader::new();
+        assert!(reader.decode_header(&header(MAX_FRAME_LENGTH, FRAME_VERSION, tag)).is_ok());
+        let too_long = header(MAX_FRAME_LENGTH + 1, FRAME_VERSION, tag);
+        assert!(reader.decode_header(&too_long).is_err());
+        let sealed = header(MAX_FRAME_LENGTH + TAG_LENGTH, FRAME_VERSION, tag);
+        assert!(reader.decode_header(&sealed).is_err());
+        reader.set_cipher(cipher(true));
+        assert!(reader.decode_header(&sealed).is_ok());
+        let too_long = header(MAX_FRAME_LENGTH + TAG_LENGTH + 1, FRAME_VERSION, tag);
+        assert!(reader.decode_header(&too_long).is_err());
+
+        let mut writer = FrameWriter::new();
+        let frame = Frame::new(MessageType::WriteFile, vec![0; MAX_FRAME_LENGTH + 1]);
+        assert!(writer.encode(frame).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_types() {
+        let reader = FrameReader::new();
+        let tag = MessageType::Stat.tag();
+        let error = reader.decode_header(&header(0, FRAME_VERSION + 1, tag)).unwrap_err();
+        let expected = format!("Frame Error: unsupported frame version {}", FRAME_VERSION + 1);
+        assert_eq!(error.to_string(), expected);
+        let error = reader.decode_header(&header(0, FRAME_VERSION, 0xff)).unwrap_err();
+        assert_eq!(error.to_string(), "Frame Error: unknown message type 255");
+    }
+
+    #[test]
+    fn reports_truncated_frames_as_closed() {
+        let mut writer = FrameWriter::new();
+        let mut bytes = writer.encode(Frame::new(MessageType::Stat, b"payload".to_vec())).unwrap();
+        bytes.pop();
+        let error = FrameReader::new().read_frame(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(error.is_closed());
+    }
+
+    #[test]
+    fn bounds_handshake_lines() {
+        let max = MAX_LINE_LENGTH as usize;
+        let longest = "a".repeat(max - 1) + "\nnext\n";
+        let mut reader = Cursor::new(longest.as_bytes());
+        let mut line = String::new();
+        assert_eq!(read_handshake_line(&mut reader, &mut line).unwrap(), max);
+        line.clear();
+        assert_eq!(read_handshake_line(&mut reader, &mut line).unwrap(), 5);
+        assert_eq!(line, "next\n");
+        line.clear();
+        assert_eq!(read_handshake_line(&mut reader, &mut line).unwrap(), 0);
+
+        let too_long = "a".repeat(max) + "\n";
+        let mut line = String::new();
+        let error = read_handshake_line(&mut Cursor::new(too_long.as_bytes()), &mut line);
+        assert_eq!(error.unwrap_err().kind(), IoErrorKind::InvalidData);
+
+        let unterminated = "a".repeat(max - 1);
+        let mut line = String::new();
+        let read = read_handshake_line(&mut Cursor::new(unterminated.as_bytes()), &mut line);
+        assert_eq!(read.unwrap(), max - 1);
+    }
+
+    #[test]
+    fn parses_challenge_lines() {
+        let challenge = generate_challenge();
+        assert_eq!(challenge.len(), CHALLENGE_LENGTH);
+        assert_eq!(parse_challenge(&challenge_line(&challenge)), Some(challenge));
+        assert_eq!(parse_challenge("Challenge is: none\n"), None);
+        assert_eq!(parse_challenge("Challenge is: \"not base64!\"\n"), None);
+    }
+
+    #[test]
+    fn checks_server_proofs() {
+        let key = b"server key".to_vec();
+        let (session, challenge) = (vec![1; 8], vec![2; 8]);
+        let line = "Protocol accepted: 1; features: encryption";
+        let proof = server_proof(&key, "cli1", &session, &challenge, line);
+        assert!(check_server_proof(&key, "cli1", &session, &challenge, line, &proof));
+        assert!(!check_server_proof(&key, "cli2", &session, &challenge, line, &proof));
+        assert!(!check_server_proof(&key, "cli1", &vec![3; 8], &challenge, line, &proof));
+        let other_key = b"other key".to_vec();
+        assert!(!check_server_proof(&other_key, "cli1", &session, &challenge, line, &proof));
+        let downgraded = "Protocol accepted: 1; features: ";
+        assert!(!check_server_proof(&key, "cli1", &session, &challenge, downgraded, &proof));
     }
 }
@@ -0,0 +1,340 @@
+//! This module gives access to remote files through the `std::io` traits. A `RfsFile` is a file
+//! opened on the server, of which it keeps track of a position. It reads or writes at that
+//! position with `ReadHandle` and `WriteHandle` requests, so that remote files can be used
+//! wherever a `Read`, `Write` or `Seek` is expected.
+
+use crate::message::Open;
+use crate::rfs_client::RfsClientSession;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::Result as IoResult;
+
+/// Options to open a remote file, with the same meaning as those of `std::fs::OpenOptions`.
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl OpenOptions {
+    /// Returns options with every option unset.
+    pub fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+        }
+    }
+
+    /// Allow reading the file.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Allow writing the file.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Write at the end of the file, whatever the position. The position is not moved by writes.
+    /// Implies `write`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncate the file when it is opened. Requires `write`.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file if it does not exist. Requires `write` or `append`.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing if it already exists. Requires `write` or `append`.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Returns the request opening `path` with these options.
+    pub(crate) fn to_message(&self, path: &str) -> Open {
+        Open::new(
+            path,
+            self.read,
+            self.write,
+            self.append,
+            self.truncate,
+            self.create,
+            self.create_new,
+        )
+    }
+
+    fn is_writable(&self) -> bool {
+        self.write || self.append
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.read || self.is_writable()) &&
+            (self.is_writable() || !(self.truncate || self.create || self.create_new)) &&
+            !(self.append && self.truncate)
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions::new()
+    }
+}
+
+/// A remote file opened by `RfsClientSession::open`. The file borrows the session: each read or
+/// write is a request to the server. The file is closed on the server when it is dropped.
+pub struct RfsFile<'a> {
+    session: &'a mut RfsClientSession,
+    handle: u64,
+    options: OpenOptions,
+    position: u64,
+    closed: bool,
+}
+
+impl<'a> RfsFile<'a> {
+    /// Open the remote file `path` with `options`.
+    pub(crate) fn open(
+        session: &'a mut RfsClientSession,
+        path: &str,
+        options: &OpenOptions,
+    ) -> IoResult<RfsFile<'a>> {
+        if !options.is_valid() {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "Invalid open options"));
+        }
+        let handle = session.open_handle(path, options)?;
+        Ok(RfsFile {
+            session: session,
+            handle: handle,
+            options: options.clone(),
+            position: 0,
+            closed: false,
+        })
+    }
+
+    /// Returns the handle identifying the file on the server.
+    pub fn get_handle(&self) -> u64 {
+        self.handle
+    }
+
+    /// Returns the size of the file, as currently stored by the server.
+    pub fn get_size(&mut self) -> IoResult<u64> {
+        Ok(self.session.stat_handle(self.handle)?.get_size())
+    }
+
+    /// Truncate (or extend with zeros) the file to `length` bytes. The position is not changed.
+    pub fn set_len(&mut self, length: u64) -> IoResult<()> {
+        if !self.options.is_writable() {
+            return Err(not_opened_for("writing"));
+        }
+        Ok(self.session.truncate_handle(self.handle, length)?)
+    }
+
+    /// Close the file on the server. Unlike dropping the file, this reports whether the file
+    /// could be closed.
+    pub fn close(mut self) -> IoResult<()> {
+        self.closed = true;
+        Ok(self.session.close_handle(self.handle)?)
+    }
+}
+
+impl Read for RfsFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if !self.options.read {
+            return Err(not_opened_for("reading"));
+        }
+        // Servers may return fewer bytes than requested before the end of the file: ask again
+        // until the buffer is filled or the end of the file is reached.
+        let length = cmp::min(buf.len(), self.session.get_chunk_length());
+        let mut read = 0;
+        while read < length {
+            let requested = (length - read) as u64;
+            let (data, eof) = self.session.read_handle(self.handle, self.position, requested)?;
+            let n = cmp::min(data.len(), length - read);
+            buf[read..read + n].copy_from_slice(&data[..n]);
+            self.position += n as u64;
+            read += n;
+            if eof || n == 0 {
+                break;
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Write for RfsFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if !self.options.is_writable() {
+            return Err(not_opened_for("writing"));
+        }
+        let length = cmp::min(buf.len(), self.session.get_chunk_length());
+        if length == 0 {
+            return Ok(0);
+        }
+        let content = buf[..length].to_vec();
+        let written = self.session.write_handle(self.handle, self.position, content)?;
+        // In append mode, the server writes at the end of the file whatever the position, which
+        // is left unchanged since the end of the file is not known.
+        if !self.options.append {
+            self.position += written;
+        }
+        Ok(written as usize)
+    }
+
+    /// Writes are sent to the server at once: there is nothing to flush.
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl Seek for RfsFile<'_> {
+    fn seek(&mut self, position: SeekFrom) -> IoResult<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => (self.get_size()?, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            )),
+        }
+    }
+}
+
+impl Drop for RfsFile<'_> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.session.close_handle(self.handle) {
+            warn!("Could not close remote file {}. Reason: {}", self.handle, e);
+        }
+    }
+}
+
+fn not_opened_for(operation: &str) -> IoError {
+    IoError::new(
+        IoErrorKind::PermissionDenied,
+        format!("The file is not opened for {}", operation),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RfsConfig;
+    use crate::message::Status;
+    use crate::rfs_client::Client;
+    use crate::rfs_server::{RfsServer, Server};
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use tempfile::TempDir;
+
+    const CONTENT: &[u8] = b"0123456789";
+
+    /// Start a server exporting a new temporary directory, which holds the file `file` of
+    /// content `CONTENT`, and returns the directory and a session of a client of the server.
+    fn connect() -> (TempDir, RfsClientSession) {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("file"), CONTENT).unwrap();
+        let config = |port: u16| {
+            let content = format!(
+                "server:srv1:enl4d3Z1:127.0.0.1:{}:::{}\nclient:cli1:MTIzNDU2\n",
+                port,
+                root.path().display()
+            );
+            let (conf, errors) = RfsConfig::parse_content(Path::new("rfs_config"), &content);
+            assert!(errors.is_empty());
+            conf
+        };
+        let server =
+            RfsServer::bound_to(String::from("srv1"), config(1), String::from("127.0.0.1:0"))
+                .unwrap();
+        let port = server.get_local_address().unwrap().port();
+        thread::spawn(move || server.listen());
+        let mut session =
+            RfsClientSession::new(String::from("srv1"), String::from("cli1"), config(port))
+                .unwrap();
+        session.connect().unwrap();
+        (root, session)
+    }
+
+    #[test]
+    fn reads_fill_the_buffer() {
+        let (_root, mut session) = connect();
+        session.set_chunk_length(4);
+        let mut file = session.open("file", OpenOptions::new().read(true)).unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(file.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"012");
+        let mut buf = [0; 8];
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"3456");
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"789");
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn appends_without_moving_the_position() {
+        let (root, mut session) = connect();
+        let mut file = session.open("file", OpenOptions::new().read(true).append(true)).unwrap();
+        file.write_all(b"ab").unwrap();
+        assert_eq!(file.stream_position().unwrap(), 0);
+        file.write_all(b"cd").unwrap();
+        let mut buf = [0; 2];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"01");
+        file.close().unwrap();
+        assert_eq!(fs::read(root.path().join("file")).unwrap(), b"0123456789abcd");
+    }
+
+    #[test]
+    fn rejects_seeks_before_the_start() {
+        let (_root, mut session) = connect();
+        let mut file = session.open("file", OpenOptions::new().read(true)).unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-2)).unwrap(), 8);
+        let error = file.seek(SeekFrom::Current(-9)).unwrap_err();
+        assert_eq!(error.kind(), IoErrorKind::InvalidInput);
+        let error = file.seek(SeekFrom::End(-11)).unwrap_err();
+        assert_eq!(error.kind(), IoErrorKind::InvalidInput);
+        assert_eq!(file.stream_position().unwrap(), 8);
+    }
+
+    #[test]
+    fn closes_the_handle_when_dropped() {
+        let (_root, mut session) = connect();
+        let handle = session.open("file", OpenOptions::new().read(true)).unwrap().get_handle();
+        let error = session.read_handle(handle, 0, 1).unwrap_err();
+        assert_eq!(error.get_status(), Some(Status::BadHandle));
+    }
+}
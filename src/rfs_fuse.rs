@@ -0,0 +1,490 @@
+//! This module exposes the directory exported by a server as a local filesystem, through FUSE.
+//! Each operation of the kernel is mapped to requests of a `RfsClientSession`.
+//!
+//! Inodes are allocated by the client, on the first lookup of each path, and are never reused
+//! during a mount (see `inode_table::InodeTable`). Files opened by the kernel are opened on the
+//! server, and read and written through their handle. If the server does not support handles,
+//! reads and writes are sent with the path of the file instead.
+
+use crate::inode_table::InodeTable;
+use crate::message::{FileKind, FileStat, Status, Timestamp};
+use crate::protocol::HANDLES;
+use crate::rfs_client::{RfsClientError, RfsClientSession};
+use crate::rfs_file::OpenOptions;
+use fuse::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+           ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request};
+use libc::{c_int, EACCES, EBADF, EEXIST, EINVAL, EIO, EISDIR, EMFILE, ENOENT, ENOTDIR, ENOTEMPTY,
+           ENOTSUP, O_ACCMODE, O_APPEND, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use time::Timespec;
+
+/// How long the kernel may cache attributes and entries.
+const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
+
+/// File handle given to the kernel for files opened without a server handle. Servers number
+/// handles from 1.
+const NO_HANDLE: u64 = 0;
+
+/// A remote file being read or written: by its handle if it was opened on the server, by its
+/// path otherwise.
+enum RemoteFile {
+    Handle(u64),
+    Path(String),
+}
+
+/// A filesystem backed by a server. The session must be connected.
+pub struct RfsFilesystem {
+    session: RfsClientSession,
+    inodes: InodeTable,
+    uid: u32,
+    gid: u32,
+}
+
+impl RfsFilesystem {
+    /// Create a filesystem serving the files of `session`. Files are owned by `uid` and `gid`,
+    /// typically those of the user mounting the filesystem.
+    pub fn new(session: RfsClientSession, uid: u32, gid: u32) -> Self {
+        RfsFilesystem {
+            session: session,
+            inodes: InodeTable::new(),
+            uid: uid,
+            gid: gid,
+        }
+    }
+
+    /// Returns the attributes of the remote file `path`, whose inode is `inode`.
+    fn attributes(&mut self, inode: u64, path: &str) -> Result<FileAttr, c_int> {
+        let stat = self.session.stat(path).map_err(errno)?;
+        Ok(self.file_attr(inode, &stat))
+    }
+
+    fn file_attr(&self, inode: u64, stat: &FileStat) -> FileAttr {
+        let kind = file_type(stat.get_kind());
+        FileAttr {
+            ino: inode,
+            size: stat.get_size(),
+            blocks: stat.get_size().div_ceil(512),
+            atime: timespec(stat.get_accessed()),
+            mtime: timespec(stat.get_modified()),
+            ctime: timespec(stat.get_modified()),
+            crtime: timespec(stat.get_modified()),
+            kind: kind,
+            perm: (stat.get_mode() & 0o7777) as u16,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    /// Returns the entry of `name` in the directory `parent`, allocating its inode. The entry is
+    /// a lookup of the kernel, until it forgets it.
+    fn entry(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, c_int> {
+        let path = self.inodes.child_path(parent, name).ok_or(ENOENT)?;
+        let stat = self.session.stat(&path).map_err(errno)?;
+        let inode = self.inodes.lookup(&path);
+        Ok(self.file_attr(inode, &stat))
+    }
+
+    fn path(&self, inode: u64) -> Result<String, c_int> {
+        self.inodes.get_path(inode).cloned().ok_or(ENOENT)
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<String, c_int> {
+        self.inodes.child_path(parent, name).ok_or(ENOENT)
+    }
+
+    /// Returns whether files can be opened on the server.
+    fn has_handles(&self) -> bool {
+        self.session.get_protocol().is_some_and(|p| p.has_feature(HANDLES))
+    }
+
+    /// Returns the file `fh`, or the file `inode` if it was opened without a handle.
+    fn remote_file(&self, inode: u64, fh: u64) -> Result<RemoteFile, c_int> {
+        if fh == NO_HANDLE {
+            self.path(inode).map(RemoteFile::Path)
+        } else {
+            Ok(RemoteFile::Handle(fh))
+        }
+    }
+
+    /// Open `path` on the server with the kernel `flags`, creating it if `create` is set.
+    /// Returns `NO_HANDLE` if the server does not support handles.
+    fn open_file(&mut self, path: &str, flags: u32, create: bool) -> Result<u64, c_int> {
+        if !self.has_handles() {
+            return Ok(NO_HANDLE);
+        }
+        let mut options = open_options(flags);
+        options.create(create);
+        let handle = self.session.open_handle(path, &options).map_err(errno)?;
+        // Files can not be opened to be both truncated and appended to: truncate them now.
+        let flags = flags as c_int;
+        if flags & O_TRUNC != 0 && flags & O_APPEND != 0 {
+            if let Err(e) = self.session.truncate_handle(handle, 0) {
+                self.close_or_warn(handle);
+                return Err(errno(e));
+            }
+        }
+        Ok(handle)
+    }
+
+    fn close_or_warn(&mut self, handle: u64) {
+        if let Err(e) = self.session.close_handle(handle) {
+            warn!("Could not close remote file {}. Reason: {}", handle, e);
+        }
+    }
+
+    /// Read at most `size` bytes of `file` at `offset`, in as many requests as needed.
+    fn read_chunks(
+        &mut self,
+        file: &RemoteFile,
+        offset: u64,
+        size: usize,
+    ) -> Result<Vec<u8>, c_int> {
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            let length = (size - data.len()).min(self.session.get_chunk_length()) as u64;
+            let position = offset + data.len() as u64;
+            let (chunk, eof) = match *file {
+                RemoteFile::Handle(handle) => self.session.read_handle(handle, position, length),
+                RemoteFile::Path(ref path) => self.session.read_at(path, position, length),
+            }.map_err(errno)?;
+            data.extend_from_slice(&chunk);
+            if eof || chunk.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Write `data` in `file` at `offset`, in as many requests as needed.
+    fn write_chunks(&mut self, file: &RemoteFile, offset: u64, data: &[u8]) -> Result<u64, c_int> {
+        let mut written = 0;
+        for chunk in data.chunks(self.session.get_chunk_length()) {
+            let position = offset + written;
+            let n = match *file {
+                RemoteFile::Handle(handle) => {
+                    self.session.write_handle(handle, position, chunk.to_vec())
+                }
+                RemoteFile::Path(ref path) => self.session.write_at(path, position, chunk.to_vec()),
+            }.map_err(errno)?;
+            written += n;
+            if n < chunk.len() as u64 {
+                break;
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Filesystem for RfsFilesystem {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.entry(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.inodes.forget(ino, nlookup);
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.path(ino).and_then(|path| self.attributes(ino, &path)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        fh: Option<u64>,
+        _crtime: Option<Timespec>,
+        _chgtime: Option<Timespec>,
+        _bkuptime: Option<Timespec>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let result = self.path(ino).and_then(|path| {
+            match (size, fh) {
+                (Some(size), Some(fh)) if fh != NO_HANDLE => {
+                    self.session.truncate_handle(fh, size).map_err(errno)?
+                }
+                (Some(size), _) => self.session.truncate(&path, size).map_err(errno)?,
+                (None, _) => (),
+            }
+            if atime.is_some() || mtime.is_some() {
+                let stat = self.session.stat(&path).map_err(errno)?;
+                let accessed = atime.map_or(stat.get_accessed(), timestamp);
+                let modified = mtime.map_or(stat.get_modified(), timestamp);
+                self.session.set_times(&path, accessed, modified).map_err(errno)?;
+            }
+            self.attributes(ino, &path)
+        });
+        match result {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mkdir(&mut self, _req: &Request, parent: u64, name: &OsStr, _mode: u32, reply: ReplyEntry) {
+        let result = self.child_path(parent, name)
+            .and_then(|path| self.session.mkdir(&path).map_err(errno))
+            .and_then(|_| self.entry(parent, name));
+        match result {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child_path(parent, name).and_then(|path| {
+            self.session.unlink(&path).map_err(errno)?;
+            self.inodes.remove(&path);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child_path(parent, name).and_then(|path| {
+            self.session.rmdir(&path).map_err(errno)?;
+            self.inodes.remove(&path);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.child_path(parent, name).and_then(|from| {
+            let to = self.child_path(newparent, newname)?;
+            self.session.rename(&from, &to).map_err(errno)?;
+            self.inodes.rename(&from, &to);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        let result = self.path(ino)
+            .and_then(|path| self.open_file(&path, flags, false));
+        match result {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if fh == NO_HANDLE {
+            return reply.ok();
+        }
+        match self.session.close_handle(fh) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        let result = self.remote_file(ino, fh).and_then(|file| {
+            self.read_chunks(&file, position(offset)?, size as usize)
+        });
+        match result {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let result = self.remote_file(ino, fh)
+            .and_then(|file| self.write_chunks(&file, position(offset)?, data));
+        match result {
+            Ok(written) => reply.written(written as u32),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let entries = match self.session.list_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) => return reply.error(errno(e)),
+        };
+        let mut listing = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (ino, FileType::Directory, "..".to_string()),
+        ];
+        for entry in entries {
+            let name = String::from_utf8_lossy(entry.get_name()).into_owned();
+            let child = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            listing.push((self.inodes.inode_of(&child), file_type(entry.get_kind()), name));
+        }
+        for (index, (inode, kind, name)) in listing.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(inode, (index + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        flags: u32,
+        reply: ReplyCreate,
+    ) {
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let created = if self.has_handles() {
+            self.open_file(&path, flags, true)
+        } else {
+            // An empty write creates the file, without truncating it if it already exists.
+            self.session.write_at(&path, 0, Vec::new()).map(|_| NO_HANDLE).map_err(errno)
+        };
+        let fh = match created {
+            Ok(fh) => fh,
+            Err(e) => return reply.error(e),
+        };
+        match self.entry(parent, name) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, fh, 0),
+            Err(e) => {
+                if fh != NO_HANDLE {
+                    self.close_or_warn(fh);
+                }
+                reply.error(e)
+            }
+        }
+    }
+}
+
+/// Returns the options to open a file with the kernel `flags` (see `open(2)`). `O_CREAT` is left
+/// to the caller, since the kernel handles it with `Filesystem::create`, and so is `O_TRUNC`
+/// along with `O_APPEND`.
+fn open_options(flags: u32) -> OpenOptions {
+    let flags = flags as c_int;
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_RDONLY => options.read(true),
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => &mut options,
+    };
+    options
+        .append(flags & O_APPEND != 0)
+        .truncate(flags & O_TRUNC != 0 && flags & O_APPEND == 0)
+        .create_new(flags & O_EXCL != 0);
+    options
+}
+
+/// Returns the position in a file of the kernel `offset`, which must not be negative.
+fn position(offset: i64) -> Result<u64, c_int> {
+    u64::try_from(offset).map_err(|_| EINVAL)
+}
+
+fn file_type(kind: FileKind) -> FileType {
+    match kind {
+        FileKind::File => FileType::RegularFile,
+        FileKind::Directory => FileType::Directory,
+        FileKind::Symlink => FileType::Symlink,
+        FileKind::Other => FileType::RegularFile,
+    }
+}
+
+fn timespec(timestamp: Timestamp) -> Timespec {
+    Timespec::new(timestamp.get_seconds() as i64, timestamp.get_nanoseconds() as i32)
+}
+
+fn timestamp(timespec: Timespec) -> Timestamp {
+    Timestamp::new(timespec.sec.max(0) as u64, timespec.nsec.max(0) as u32)
+}
+
+/// Returns the error number matching the failure of a request.
+fn errno(e: RfsClientError) -> c_int {
+    match e.get_status() {
+        Some(Status::NotFound) => ENOENT,
+        Some(Status::PermissionDenied) => EACCES,
+        Some(Status::AlreadyExists) => EEXIST,
+        Some(Status::NotADirectory) => ENOTDIR,
+        Some(Status::IsADirectory) => EISDIR,
+        Some(Status::DirectoryNotEmpty) => ENOTEMPTY,
+        Some(Status::InvalidArgument) => EINVAL,
+        Some(Status::BadHandle) => EBADF,
+        Some(Status::TooManyOpenFiles) => EMFILE,
+        Some(Status::Unsupported) => ENOTSUP,
+        _ => {
+            warn!("Request failed. Reason: {}", e);
+            EIO
+        }
+    }
+}
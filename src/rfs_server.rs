@@ -1,21 +1,61 @@
-use base64;
-use block_cipher_trait::BlockCipher;
 use blowfish::Blowfish;
-use generic_array::GenericArray;
-use rfs_common::*;
-use config::{RfsConfig, Config};
-use config::Field;
-use std::io::BufRead;
+use crate::rfs_common::*;
+use crate::config::{RfsConfig, Config};
+use crate::config::{Field, Permissions, Access};
+use crate::message::{Message, MessageType, Reply, Status};
+use crate::message::{WriteFile, WriteFileReply, ReadFile, ReadFileReply, Stat, StatReply, ListDir,
+                     ListDirReply, Mkdir, MkdirReply, Rmdir, RmdirReply, Unlink, UnlinkReply,
+                     Rename, RenameReply, Truncate, TruncateReply, SetTimes, SetTimesReply, Open,
+                     OpenReply, Close, CloseReply, ReadHandle, WriteHandle, StatHandle,
+                     TruncateHandle, FileStat, DirEntry, FileKind};
+use crate::message_signer::{MessageSigner, DefaultSigner, SignedMessage};
+use crate::frame_cipher::FrameCipher;
+use crate::live_config::LiveConfig;
+use crate::protocol::{self, Protocol};
+use crate::sandbox::Sandbox;
+#[cfg(feature = "tls")]
+use crate::tls;
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
+use std::collections::HashMap;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::io::Result as IoResult;
 use std::io::Write;
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener};
 use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::time::Duration;
+use threadpool::ThreadPool;
 
+/// Default maximal number of clients served concurrently.
+pub const DEFAULT_MAX_CLIENTS: usize = 32;
+/// Time a client has to send each line of the handshake, before it is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time an authenticated client can stay without sending requests, before it is dropped.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Maximal number of files a client can keep open at once.
+pub const MAX_OPEN_FILES: usize = 64;
 
 pub struct RfsServer {
-    name: String,
-    config: RfsConfig,
+    identity: Field,
+    config: LiveConfig,
+    sandbox: Sandbox,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<ServerConfig>>,
     listener: TcpListener,
+    pool: ThreadPool,
+}
+
+/// Serves a single client, on a worker of the `RfsServer`.
+struct ClientHandler {
+    identity: Field,
+    config: LiveConfig,
+    sandbox: Sandbox,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<ServerConfig>>,
 }
 
 pub trait Server {
@@ -29,8 +69,24 @@ impl Server for RfsServer {
             match self.listener.accept() {
                 Ok((socket, addr)) => {
                     info!("new tcp client: {:?}", addr);
-                    self.handle_client(socket);
-                    info!("Connection closed");
+                    if self.pool.active_count() + self.pool.queued_count() >=
+                        self.pool.max_count()
+                    {
+                        // Dropping the socket closes the connection.
+                        warn!("Refusing {:?}. Reason: too many clients", addr);
+                        continue;
+                    }
+                    let handler = ClientHandler {
+                        identity: self.identity.clone(),
+                        config: self.config.clone(),
+                        sandbox: self.sandbox.clone(),
+                        #[cfg(feature = "tls")]
+                        tls: self.tls.clone(),
+                    };
+                    self.pool.execute(move || {
+                        handler.handle_client(socket);
+                        info!("Connection with {:?} closed", addr);
+                    });
                 }
                 Err(e) => warn!("error: {:?}", e),
             }
@@ -39,22 +95,67 @@ impl Server for RfsServer {
 }
 
 impl RfsServer {
+    /// Create the server named `name` in `config`, listening on the address and port of its
+    /// entry.
     pub fn new(name: String, config: RfsConfig) -> Option<Self> {
+        RfsServer::create(name, config, None)
+    }
+
+    /// Create the server named `name` in `config`, listening on `socket` (e.g. `0.0.0.0:4242`)
+    /// instead of the address and port of its entry.
+    pub fn bound_to(name: String, config: RfsConfig, socket: String) -> Option<Self> {
+        RfsServer::create(name, config, Some(socket))
+    }
+
+    fn create(name: String, config: RfsConfig, socket: Option<String>) -> Option<Self> {
         let my_conf = config.get_from_name(name);
         match my_conf {
-            Ok(&Field::Server {
-                   ref name,
+            Ok(Field::Server {
+                   name: _,
                    key: _,
-                   ref address,
-                   ref port,
+                   address,
+                   port,
+                   tls,
+                   root: _,
                }) => {
-                welcome(my_conf.unwrap().clone());
-                let socket = address.clone() + ":" + &port;
+                let identity = my_conf.unwrap().clone();
+                welcome(identity.clone());
+                let sandbox = match export_root(&identity) {
+                    Ok(sandbox) => sandbox,
+                    Err(e) => {
+                        error!("Can not create RfsServer. Reason: {}", e);
+                        return None;
+                    }
+                };
+                #[cfg(feature = "tls")]
+                let tls = match tls.as_ref().map(tls::server_config) {
+                    Some(Ok(tls)) => Some(tls),
+                    Some(Err(e)) => {
+                        error!("Can not create RfsServer. Reason: {}", e);
+                        return None;
+                    }
+                    None => None,
+                };
+                #[cfg(not(feature = "tls"))]
+                {
+                    if tls.is_some() {
+                        error!(
+                            "Can not create RfsServer. Reason: {} requires TLS",
+                            identity.get_name()
+                        );
+                        return None;
+                    }
+                }
+                let socket = socket.unwrap_or(address.clone() + ":" + port);
                 match TcpListener::bind(socket) {
                     Ok(l) => Some(RfsServer {
-                        name: name.clone(),
-                        config: config.clone(),
+                        identity: identity,
+                        config: LiveConfig::new(config.clone()),
+                        sandbox: sandbox,
+                        #[cfg(feature = "tls")]
+                        tls: tls,
                         listener: l,
+                        pool: ThreadPool::new(DEFAULT_MAX_CLIENTS),
                     }),
                     Err(e) => {
                         error!("Can not create RfsServer. Reason: {}", e);
@@ -62,7 +163,12 @@ impl RfsServer {
                     }
                 }
             }
-            Ok(&Field::Client { ref name, key: _ }) => {
+            Ok(Field::Client {
+                   name,
+                   key: _,
+                   root: _,
+                   permissions: _,
+               }) => {
                 error!("Item {} is a client", name);
                 None
             }
@@ -73,10 +179,96 @@ impl RfsServer {
         }
     }
 
+    /// Returns the configuration of the server, which can be reloaded while it serves clients.
+    /// The identity, address and export root of the server are not reloaded.
+    pub fn get_config(&self) -> &LiveConfig {
+        &self.config
+    }
+
+    /// Returns the address the server listens on, e.g. to find the port chosen by the system
+    /// when the server is bound to port 0.
+    pub fn get_local_address(&self) -> IoResult<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Set the maximal number of clients served concurrently. Further connections are closed
+    /// until a client leaves.
+    pub fn set_max_clients(&mut self, max_clients: usize) {
+        // Idle workers of the current pool would keep serving clients until their next job, so
+        // the pool is replaced rather than resized.
+        self.pool = ThreadPool::new(max_clients);
+    }
+}
+
+impl ClientHandler {
+    /// Serve a client, over TLS if the server requires it.
     fn handle_client(&self, stream: TcpStream) {
-        match self.auth_client::<Client>(stream) {
-            Some((client, _)) => {
+        // Timeouts are options of the socket, so they also apply once it is wrapped in TLS.
+        let socket = match stream.try_clone() {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Could not set up the connection. Reason: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT)) {
+            warn!("Could not set the handshake timeout. Reason: {}", e);
+            return;
+        }
+        #[cfg(feature = "tls")]
+        {
+            if let Some(ref config) = self.tls {
+                match tls::accept(config, stream) {
+                    Ok(stream) => self.handle_stream(stream, &socket),
+                    Err(e) => warn!("Could not set up TLS. Reason: {}", e),
+                }
+                return;
+            }
+        }
+        self.handle_stream(stream, &socket)
+    }
+
+    /// Serve a client on `stream`, carried by `socket`.
+    fn handle_stream<S: Read + Write>(&self, stream: S, socket: &TcpStream) {
+        let mut stream = get_buf_reader(stream);
+        let config = self.config.get();
+        let protocol = match negotiate(&mut stream, config.is_encryption_enabled()) {
+            Some(protocol) => protocol,
+            None => return,
+        };
+        let protocol_line = protocol.get_accepted_line();
+        match self.auth_client(&config, &mut stream, &protocol_line) {
+            Some((client, challenge, client_challenge)) => {
                 info!("Client {} authenticated.", client.get_name());
+                if let Err(e) = socket.set_read_timeout(Some(IDLE_TIMEOUT)) {
+                    warn!("Could not set the idle timeout. Reason: {}", e);
+                    return;
+                }
+                let mut frame_reader = FrameReader::new();
+                let mut frame_writer = FrameWriter::new();
+                if protocol.has_feature(protocol::ENCRYPTION) {
+                    let secret = client.get_secret();
+                    frame_reader.set_cipher(FrameCipher::client_to_server(
+                        secret,
+                        &challenge,
+                        &client_challenge,
+                        &protocol_line,
+                    ));
+                    frame_writer.set_cipher(FrameCipher::server_to_client(
+                        secret,
+                        &challenge,
+                        &client_challenge,
+                        &protocol_line,
+                    ));
+                }
+                let sandbox = match client_sandbox(&self.sandbox, client) {
+                    Some(sandbox) => sandbox,
+                    None => return,
+                };
+                let signer = DefaultSigner::new(client.get_secret().clone(), challenge);
+                let handler =
+                    RequestHandler::new(signer, sandbox, client_permissions(client), protocol);
+                self.serve_requests(&mut stream, client, handler, frame_reader, frame_writer);
             }
             None => {
                 warn!("Authentication failure");
@@ -84,71 +276,141 @@ impl RfsServer {
 
         }
     }
-    /*
-    fn auth_client<I: Identity<Name = String>>(
+
+    /// Read signed requests from an authenticated client until the connection is closed, and
+    /// execute them. The session is terminated if the client has been revoked since.
+    fn serve_requests<S, M>(
         &self,
-        mut stream: TcpStream,
-    ) -> Option<(&I, Blowfish)> {
-    */
-    fn auth_client<I: Identity<Name = String>>(
+        stream: &mut BufReader<S>,
+        client: &Field,
+        mut handler: RequestHandler<M>,
+        mut frame_reader: FrameReader,
+        mut frame_writer: FrameWriter,
+    ) where
+        S: Read + Write,
+        M: MessageSigner,
+    {
+        loop {
+            let reply = match frame_reader.read_frame(stream) {
+                Ok(_) if self.config.is_revoked(client) => {
+                    warn!("Dropping client {}. Reason: revoked", client.get_name());
+                    return;
+                }
+                Ok(frame) => {
+                    match handler.handle(frame) {
+                        Some(reply) => reply,
+                        None => return,
+                    }
+                }
+                Err(ref e) if e.is_closed() => return,
+                Err(e) => {
+                    warn!("Dropping client. Reason: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = frame_writer.write_frame(stream.get_mut(), reply) {
+                warn!("Could not send reply. Reason: {}", e);
+                return;
+            }
+        }
+    }
+
+    fn auth_client<'a, S: Read + Write>(
         &self,
-        mut stream: TcpStream,
-    ) -> Option<(&Field, Blowfish)> {
+        config: &'a RfsConfig,
+        reader: &mut BufReader<S>,
+        protocol_line: &str,
+    ) -> Option<(&'a Field, SessionId, Challenge)> {
 
-        let mut reader = get_buf_reader(stream.try_clone().unwrap());
         let challenge = generate_challenge();
         let mut reader_buffer = String::new();
 
-        match self.send_id_request(stream.try_clone().unwrap(), challenge.clone()) {
+        match self.send_id_request(reader.get_mut(), challenge.clone()) {
             Ok(_) => {
-                match reader.read_line(&mut reader_buffer) {
+                match read_handshake_line(reader, &mut reader_buffer) {
                     Ok(n) => info!("Read {} bytes as identity line", n),
-                    Err(e) => warn!("Could not read identity: {}", e),
+                    Err(e) => {
+                        warn!("Could not read identity: {}", e);
+                        return None;
+                    }
                 }
-                match self.config.get_from_name(
-                    remove_newline(reader_buffer.clone()),
-                ) {
-                    Ok(client_identity_pretended) => {
+                match config.get_from_name(remove_newline(reader_buffer.clone())) {
+                    Ok(client_identity_pretended @ &Field::Client { .. }) => {
                         info!(
                             "Identity pretended: {}",
                             client_identity_pretended.get_name()
                         );
 
-                        let (client_bf, challenge_response_exp) =
+                        let (_, challenge_response_exp) =
                             client_expected_challenge_response(
                                 client_identity_pretended,
-                                challenge,
+                                challenge.clone(),
                             );
                         info!("Challenge expected: {:?}", challenge_response_exp.clone());
 
                         reader_buffer.clear();
-                        match reader.read_line(&mut reader_buffer) {
+                        match read_handshake_line(reader, &mut reader_buffer) {
                             Ok(n) => info!("Read {} bytes as challenge response line", n),
-                            Err(e) => warn!("Could not read challenge response: {}", e),
+                            Err(e) => {
+                                warn!("Could not read challenge response: {}", e);
+                                return None;
+                            }
                         }
-                        let challenge_response_buf: Challenge = base64::decode(&remove_newline(
-                            reader_buffer.clone(),
-                        )).unwrap_or_else(|e| {
-                            error!("Can not decode {}: {}", reader_buffer, e);
-                            panic!()
-                        });
+                        let challenge_response_buf: Challenge =
+                            match base64::decode(&remove_newline(reader_buffer.clone())) {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    warn!("Can not decode {}: {}", reader_buffer, e);
+                                    self.send_auth_failure(reader.get_mut());
+                                    return None;
+                                }
+                            };
                         info!("Challenge received: {:?}", challenge_response_buf.clone());
 
-                        if challenge_response_buf == challenge_response_exp {
-                            info!("Client authenticated");
-                            stream.write("Client authenticated\n".as_bytes()).expect(
-                                "Error",
-                            );
-                            Some((client_identity_pretended, client_bf))
-                        } else {
-                            info!("Authentication failure. Aborting.");
-                            let buf = "Authentication failure. Aborting.\n".to_string();
-                            match stream.write(buf.as_bytes()) {
-                                Ok(_) => info!("NAUTH sent"),
-                                Err(e) => warn!("NAUTH not sent: {}", e),
-                            };
-                            None
+                        reader_buffer.clear();
+                        match read_handshake_line(reader, &mut reader_buffer) {
+                            Ok(n) => info!("Read {} bytes as client challenge line", n),
+                            Err(e) => {
+                                warn!("Could not read client challenge: {}", e);
+                                return None;
+                            }
                         }
+
+                        match parse_challenge(&reader_buffer) {
+                            Some(ref client_challenge)
+                                if challenge_response_buf == challenge_response_exp => {
+                                info!("Client authenticated");
+                                let proof = server_proof(
+                                    self.identity.get_secret(),
+                                    client_identity_pretended.get_name(),
+                                    &challenge,
+                                    client_challenge,
+                                    protocol_line,
+                                );
+                                let verdict = AUTH_SUCCESS.to_string() + &proof_line(&proof);
+                                match reader.get_mut().write_all(verdict.as_bytes()) {
+                                    Ok(()) => {
+                                        Some((
+                                            client_identity_pretended,
+                                            challenge,
+                                            client_challenge.clone(),
+                                        ))
+                                    }
+                                    Err(e) => {
+                                        warn!("AUTH not sent: {}", e);
+                                        None
+                                    }
+                                }
+                            }
+                            _ => {
+                                self.send_auth_failure(reader.get_mut());
+                                None
+                            }
+                        }
+                    }
+                    Ok(field) => {
+                        warn!("Item {} is not a client", field.get_name());
+                        None
                     }
                     Err(_) => None, 
                 }
@@ -161,37 +423,826 @@ impl RfsServer {
     }
 
 
-    fn send_id_request(&self, mut stream: TcpStream, challenge: Challenge) -> IoResult<usize> {
+    fn send_auth_failure<W: Write>(&self, stream: &mut W) {
+        info!("Authentication failure. Aborting.");
+        match stream.write_all(AUTH_FAILURE.as_bytes()) {
+            Ok(()) => info!("NAUTH sent"),
+            Err(e) => warn!("NAUTH not sent: {}", e),
+        };
+    }
+
+    fn send_id_request<W: Write>(&self, stream: &mut W, challenge: Challenge) -> IoResult<usize> {
         info!("Challenge proposed: {:?}", challenge.clone());
-        let buf = "Please identify yourself\n".to_string() + &"Challenge is: \"" +
-            &base64::encode(&challenge) + &"\"\n";
-        stream.write(buf.as_bytes())
+        stream.write(id_request(&challenge).as_bytes())
     }
 }
 
-fn remove_newline(s: String) -> String {
-    let mut my_s = s.clone();
-    my_s.pop();
-    my_s
+/// Negotiate the protocol with a client, which must choose encryption if `encryption`. Returns
+/// `None` if the client and the server have no protocol in common, after telling the client why.
+fn negotiate<S: Read + Write>(stream: &mut BufReader<S>, encryption: bool) -> Option<Protocol> {
+    if let Err(e) = stream.get_mut().write_all(protocol::greeting().as_bytes()) {
+        warn!("Protocol greeting not sent: {}", e);
+        return None;
+    }
+    let mut choice = String::new();
+    if let Err(e) = read_handshake_line(stream, &mut choice) {
+        warn!("Could not read protocol choice: {}", e);
+        return None;
+    }
+    let (answer, protocol) = match protocol::accept(&choice, encryption) {
+        Ok((protocol, line)) => (line, Some(protocol)),
+        Err((e, line)) => {
+            warn!("Protocol rejected. Reason: {}", e);
+            (line, None)
+        }
+    };
+    if let Err(e) = stream.get_mut().write_all(answer.as_bytes()) {
+        warn!("Protocol answer not sent: {}", e);
+        return None;
+    }
+    if let Some(ref protocol) = protocol {
+        info!("Protocol negotiated: {:?}", protocol);
+    }
+    protocol
+}
+
+/// Executes the requests of an authenticated client. It does not perform any I/O on the
+/// connection, so that both the blocking and the asynchronous servers can use it.
+pub(crate) struct RequestHandler<M: MessageSigner> {
+    signer: M,
+    sandbox: Sandbox,
+    permissions: Permissions,
+    protocol: Protocol,
+    handles: HandleTable,
+}
+
+impl<M: MessageSigner> RequestHandler<M> {
+    pub(crate) fn new(
+        signer: M,
+        sandbox: Sandbox,
+        permissions: Permissions,
+        protocol: Protocol,
+    ) -> Self {
+        RequestHandler {
+            signer: signer,
+            sandbox: sandbox,
+            permissions: permissions,
+            protocol: protocol,
+            handles: HandleTable::new(),
+        }
+    }
+
+    /// Execute the request carried by `frame`, and returns the frame of the reply. Returns `None`
+    /// if the client should be dropped.
+    pub(crate) fn handle(&mut self, frame: Frame) -> Option<Frame> {
+        let signed_message = match SignedMessage::from_frame(frame) {
+            Some(m) => m,
+            None => {
+                warn!("Dropping client. Reason: malformed signed message");
+                return None;
+            }
+        };
+        if let Err(e) = self.signer.assert(&signed_message) {
+            warn!("Dropping client. Reason: {}", e);
+            return None;
+        }
+        let message_type = signed_message.get_message_type();
+        let refusal = if !self.protocol.allows(message_type) {
+            warn!("Request {:?} belongs to a feature which was not negotiated", message_type);
+            Some(Status::Unsupported)
+        } else if !self.permissions.allows(message_type) {
+            warn!("Request {:?} is not permitted to the client", message_type);
+            Some(Status::PermissionDenied)
+        } else {
+            None
+        };
+        let signer = &mut self.signer;
+        let sandbox = &self.sandbox;
+        let permissions = &self.permissions;
+        let handles = &mut self.handles;
+        let message = &signed_message;
+        let signed_reply = match message_type {
+            MessageType::WriteFile => {
+                reply(message, signer, refusal, |m| execute_write_file(sandbox, m))
+            }
+            MessageType::ReadFile => {
+                reply(message, signer, refusal, |m| execute_read_file(sandbox, m))
+            }
+            MessageType::Stat => reply(message, signer, refusal, |m| execute_stat(sandbox, m)),
+            MessageType::ListDir => {
+                reply(message, signer, refusal, |m| execute_list_dir(sandbox, m))
+            }
+            MessageType::Mkdir => reply(message, signer, refusal, |m| execute_mkdir(sandbox, m)),
+            MessageType::Rmdir => reply(message, signer, refusal, |m| execute_rmdir(sandbox, m)),
+            MessageType::Unlink => {
+                reply(message, signer, refusal, |m| execute_unlink(sandbox, m))
+            }
+            MessageType::Rename => {
+                reply(message, signer, refusal, |m| execute_rename(sandbox, m))
+            }
+            MessageType::Truncate => {
+                reply(message, signer, refusal, |m| execute_truncate(sandbox, m))
+            }
+            MessageType::SetTimes => {
+                reply(message, signer, refusal, |m| execute_set_times(sandbox, m))
+            }
+            MessageType::Open => {
+                reply(message, signer, refusal, |m| {
+                    execute_open(sandbox, permissions, handles, m)
+                })
+            }
+            MessageType::Close => reply(message, signer, refusal, |m| execute_close(handles, m)),
+            MessageType::ReadHandle => {
+                reply(message, signer, refusal, |m| execute_read_handle(handles, m))
+            }
+            MessageType::WriteHandle => {
+                reply(message, signer, refusal, |m| execute_write_handle(handles, m))
+            }
+            MessageType::StatHandle => {
+                reply(message, signer, refusal, |m| execute_stat_handle(handles, m))
+            }
+            MessageType::TruncateHandle => {
+                reply(message, signer, refusal, |m| execute_truncate_handle(handles, m))
+            }
+            t => {
+                warn!("Dropping client. Reason: unexpected message type {:?}", t);
+                return None;
+            }
+        };
+        match signed_reply {
+            Some(signed_reply) => Some(signed_reply.into_frame()),
+            None => {
+                warn!("Dropping client. Reason: could not sign reply");
+                None
+            }
+        }
+    }
+}
+
+/// A file opened by a client, along with its path for the logs.
+struct OpenFile {
+    file: File,
+    path: PathBuf,
+}
+
+/// The files opened by a client during its session, by handle. Files are closed when the client
+/// closes them, or at the end of the session.
+struct HandleTable {
+    files: HashMap<u64, OpenFile>,
+    next: u64,
+}
+
+impl HandleTable {
+    fn new() -> Self {
+        HandleTable {
+            files: HashMap::new(),
+            next: 1,
+        }
+    }
+
+    /// Returns a new handle for `file`, unless too many files are open.
+    fn insert(&mut self, file: File, path: PathBuf) -> Result<u64, Status> {
+        if self.files.len() >= MAX_OPEN_FILES {
+            return Err(Status::TooManyOpenFiles);
+        }
+        let handle = self.next;
+        self.next += 1;
+        self.files.insert(
+            handle,
+            OpenFile {
+                file: file,
+                path: path,
+            },
+        );
+        Ok(handle)
+    }
+
+    fn get(&mut self, handle: u64) -> Result<&mut OpenFile, Status> {
+        self.files.get_mut(&handle).ok_or(Status::BadHandle)
+    }
+
+    fn remove(&mut self, handle: u64) -> Result<OpenFile, Status> {
+        self.files.remove(&handle).ok_or(Status::BadHandle)
+    }
 }
 
-fn generate_challenge() -> Challenge {
-    use rand::os::OsRng;
-    use rand::Rng;
+/// Sent to the client once it is authenticated.
+pub(crate) const AUTH_SUCCESS: &str = "Client authenticated\n";
+/// Sent to the client if its authentication failed.
+pub(crate) const AUTH_FAILURE: &str = "Authentication failure. Aborting.\n";
+
+/// First lines sent to a client, asking for its identity and the response to `challenge`.
+pub(crate) fn id_request(challenge: &Challenge) -> String {
+    "Please identify yourself\n".to_string() + &challenge_line(challenge)
+}
 
-    let mut rng = OsRng::new().unwrap();
-    let mut buf: [u8; 8] = [0; 8];
-    rng.fill_bytes(&mut buf);
-    buf.to_vec()
+/// Line following `AUTH_SUCCESS`, carrying the answer of the server to the challenge of the
+/// client.
+pub(crate) fn proof_line(proof: &[u8]) -> String {
+    "Server proof is: \"".to_string() + &base64::encode(proof) + "\"\n"
 }
 
-fn client_expected_challenge_response<I>(i: &I, challenge: Challenge) -> (Blowfish, Challenge)
+/// Deserialize the request contained in `message`, execute it unless there is a `refusal`, and
+/// sign the reply. A refused request is answered with the status of the refusal.
+fn reply<M, R, F, S>(
+    message: &SignedMessage,
+    signer: &mut S,
+    refusal: Option<Status>,
+    execute: F,
+) -> Option<SignedMessage>
+where
+    M: Message,
+    R: Reply,
+    F: FnOnce(&M) -> R,
+    S: MessageSigner,
+{
+    let reply = match (M::deserialize(message.get_message()), refusal) {
+        (Some(_), Some(status)) => R::from_status(status),
+        (Some(m), None) => execute(&m),
+        (None, _) => R::from_status(Status::BadMessage),
+    };
+    let status = reply.get_status();
+    signer.sign(reply).or_else(|| {
+        warn!("Could not sign reply with status {:?}", status);
+        signer.sign(R::from_status(Status::BadMessage))
+    })
+}
+
+/// Returns the directory exported by the server `identity`: its root if it has one, the working
+/// directory otherwise.
+pub(crate) fn export_root(identity: &Field) -> IoResult<Sandbox> {
+    match identity.get_root() {
+        Some(root) => Sandbox::new(root),
+        None => {
+            let sandbox = Sandbox::new(".")?;
+            warn!(
+                "{} has no export root. Exporting {}",
+                identity.get_name(),
+                sandbox.get_root().display()
+            );
+            Ok(sandbox)
+        }
+    }
+}
+
+/// Returns the sandbox of `client`, confined to its own root if it has one, and limited to the
+/// prefixes allowed by its permissions. Returns `None` if the root of the client can not be
+/// exported.
+pub(crate) fn client_sandbox(server: &Sandbox, client: &Field) -> Option<Sandbox> {
+    let sandbox = match client.get_root() {
+        Some(root) => {
+            match server.restrict(root) {
+                Ok(sandbox) => sandbox,
+                Err(status) => {
+                    warn!("Can not export {} to {}: {:?}", root, client.get_name(), status);
+                    return None;
+                }
+            }
+        }
+        None => server.clone(),
+    };
+    match client.get_permissions() {
+        Some(permissions) => Some(sandbox.limit_to(permissions.get_prefixes())),
+        None => Some(sandbox),
+    }
+}
+
+/// Returns the permissions of `client`. Fields which are not clients have no permission.
+pub(crate) fn client_permissions(client: &Field) -> Permissions {
+    match client.get_permissions() {
+        Some(permissions) => permissions.clone(),
+        None => Permissions::new(Access::ReadOnly, Vec::new(), Some(Vec::new())),
+    }
+}
+
+/// Interprets the bytes of a path sent by the client, as a path in its sandbox. The root of the
+/// sandbox is rejected with `Status::PermissionDenied`: it can only be read with `Stat` and
+/// `ListDir`, see `requested_path_or_root`.
+fn requested_path(sandbox: &Sandbox, path: &[u8]) -> Result<PathBuf, Status> {
+    let resolved = sandbox.resolve(path)?;
+    if resolved == sandbox.get_root() {
+        warn!("Operation on the exported directory itself is not permitted");
+        return Err(Status::PermissionDenied);
+    }
+    Ok(resolved)
+}
+
+/// Like `requested_path`, but the root of the sandbox is accepted.
+fn requested_path_or_root(sandbox: &Sandbox, path: &[u8]) -> Result<PathBuf, Status> {
+    sandbox.resolve(path)
+}
+
+/// Runs `operation` on the path sent by the client, and turns its outcome into a `Status`.
+fn execute_on_path<F>(sandbox: &Sandbox, path: &[u8], action: &str, operation: F) -> Status
+where
+    F: FnOnce(&Path) -> IoResult<()>,
+{
+    match requested_path(sandbox, path) {
+        Ok(p) => {
+            match operation(&p) {
+                Ok(()) => {
+                    info!("{} {}", action, p.display());
+                    Status::Ok
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not {} {}. Reason: {}",
+                        action.to_lowercase(),
+                        p.display(),
+                        e
+                    );
+                    Status::from(&e)
+                }
+            }
+        }
+        Err(status) => status,
+    }
+}
+
+fn execute_write_file(sandbox: &Sandbox, wf: &WriteFile) -> WriteFileReply {
+    let filename = match requested_path(sandbox, wf.get_filename()) {
+        Ok(f) => f,
+        Err(status) => return WriteFileReply::from_status(status),
+    };
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&filename)
+        .and_then(|mut file| write_at(&mut file, wf.get_position(), wf.get_content()));
+    match written {
+        Ok(()) => {
+            info!(
+                "Wrote {} bytes at {} in {}",
+                wf.get_content().len(),
+                wf.get_position(),
+                filename.display()
+            );
+            WriteFileReply::new(Status::Ok, wf.get_content().len() as u64)
+        }
+        Err(e) => {
+            warn!("Could not write in {}. Reason: {}", filename.display(), e);
+            WriteFileReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_read_file(sandbox: &Sandbox, rf: &ReadFile) -> ReadFileReply {
+    let filename = match requested_path(sandbox, rf.get_filename()) {
+        Ok(f) => f,
+        Err(status) => return ReadFileReply::from_status(status),
+    };
+    let read = File::open(&filename).and_then(|mut file| {
+        read_at(&mut file, rf.get_position(), rf.get_length())
+    });
+    match read {
+        Ok((data, eof)) => {
+            info!(
+                "Read {} bytes at {} in {}",
+                data.len(),
+                rf.get_position(),
+                filename.display()
+            );
+            ReadFileReply::new(Status::Ok, data, eof)
+        }
+        Err(e) => {
+            warn!("Could not read {}. Reason: {}", filename.display(), e);
+            ReadFileReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+/// Write `content` at `position` in `file`.
+fn write_at(file: &mut File, position: u64, content: &[u8]) -> IoResult<()> {
+    file.seek(SeekFrom::Start(position))?;
+    file.write_all(content)
+}
+
+/// Read at most `length` bytes of `file` at `position`, and at most `MAX_CHUNK_LENGTH`. Returns
+/// the data read, and whether the end of the file has been reached.
+fn read_at(file: &mut File, position: u64, length: u64) -> IoResult<(Vec<u8>, bool)> {
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(position))?;
+    let mut data = Vec::new();
+    let length = length.min(MAX_CHUNK_LENGTH as u64);
+    file.take(length).read_to_end(&mut data)?;
+    let eof = position + data.len() as u64 >= size;
+    Ok((data, eof))
+}
+
+fn execute_stat(sandbox: &Sandbox, st: &Stat) -> StatReply {
+    let path = match requested_path_or_root(sandbox, st.get_path()) {
+        Ok(p) => p,
+        Err(status) => return StatReply::from_status(status),
+    };
+    match fs::metadata(&path) {
+        Ok(metadata) => StatReply::new(FileStat::from(&metadata)),
+        Err(e) => {
+            warn!("Could not stat {}. Reason: {}", path.display(), e);
+            StatReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_list_dir(sandbox: &Sandbox, ld: &ListDir) -> ListDirReply {
+    let path = match requested_path_or_root(sandbox, ld.get_path()) {
+        Ok(p) => p,
+        Err(status) => return ListDirReply::from_status(status),
+    };
+    let entries = fs::read_dir(&path).and_then(|dir| {
+        let mut entries = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            let kind = entry.file_type().map(FileKind::from)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push(DirEntry::new(name.into_bytes(), kind));
+        }
+        Ok(entries)
+    });
+    match entries {
+        Ok(entries) => ListDirReply::new(entries),
+        Err(e) => {
+            warn!("Could not list {}. Reason: {}", path.display(), e);
+            ListDirReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_mkdir(sandbox: &Sandbox, m: &Mkdir) -> MkdirReply {
+    MkdirReply::from_status(execute_on_path(sandbox, m.get_path(), "Create directory", |p| {
+        fs::create_dir(p)
+    }))
+}
+
+fn execute_rmdir(sandbox: &Sandbox, r: &Rmdir) -> RmdirReply {
+    RmdirReply::from_status(execute_on_path(sandbox, r.get_path(), "Remove directory", |p| {
+        fs::remove_dir(p)
+    }))
+}
+
+fn execute_unlink(sandbox: &Sandbox, u: &Unlink) -> UnlinkReply {
+    UnlinkReply::from_status(execute_on_path(sandbox, u.get_path(), "Remove file", |p| {
+        fs::remove_file(p)
+    }))
+}
+
+fn execute_rename(sandbox: &Sandbox, r: &Rename) -> RenameReply {
+    let to = match requested_path(sandbox, r.get_to()) {
+        Ok(t) => t,
+        Err(status) => return RenameReply::from_status(status),
+    };
+    RenameReply::from_status(execute_on_path(sandbox, r.get_from(), "Rename", |from| {
+        fs::rename(from, to)
+    }))
+}
+
+fn execute_truncate(sandbox: &Sandbox, t: &Truncate) -> TruncateReply {
+    TruncateReply::from_status(execute_on_path(sandbox, t.get_path(), "Truncate", |p| {
+        OpenOptions::new().write(true).open(p)?.set_len(
+            t.get_length(),
+        )
+    }))
+}
+
+fn execute_set_times(sandbox: &Sandbox, st: &SetTimes) -> SetTimesReply {
+    let (accessed, modified) = match (
+        st.get_accessed().to_system_time(),
+        st.get_modified().to_system_time(),
+    ) {
+        (Some(accessed), Some(modified)) => (accessed, modified),
+        _ => {
+            warn!("Invalid times {:?}", st);
+            return SetTimesReply::from_status(Status::InvalidArgument);
+        }
+    };
+    let times = FileTimes::new().set_accessed(accessed).set_modified(modified);
+    SetTimesReply::from_status(execute_on_path(sandbox, st.get_path(), "Set times of", |p| {
+        File::open(p)?.set_times(times)
+    }))
+}
+
+fn execute_open(
+    sandbox: &Sandbox,
+    permissions: &Permissions,
+    handles: &mut HandleTable,
+    o: &Open,
+) -> OpenReply {
+    if !permissions.allows_open(o.is_read(), o.is_write(), o.is_truncate()) {
+        warn!("Opening with {:?} is not permitted to the client", o);
+        return OpenReply::from_status(Status::PermissionDenied);
+    }
+    let path = match requested_path(sandbox, o.get_filename()) {
+        Ok(p) => p,
+        Err(status) => return OpenReply::from_status(status),
+    };
+    let file = match o.to_open_options().open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Could not open {}. Reason: {}", path.display(), e);
+            return OpenReply::from_status(Status::from(&e));
+        }
+    };
+    match handles.insert(file, path.clone()) {
+        Ok(handle) => {
+            info!("Opened {} as {}", path.display(), handle);
+            OpenReply::new(handle)
+        }
+        Err(status) => {
+            warn!("Could not open {}. Reason: too many open files", path.display());
+            OpenReply::from_status(status)
+        }
+    }
+}
+
+fn execute_close(handles: &mut HandleTable, c: &Close) -> CloseReply {
+    match handles.remove(c.get_handle()) {
+        Ok(open_file) => {
+            info!("Closed {}", open_file.path.display());
+            CloseReply::from_status(Status::Ok)
+        }
+        Err(status) => CloseReply::from_status(status),
+    }
+}
+
+fn execute_read_handle(handles: &mut HandleTable, rh: &ReadHandle) -> ReadFileReply {
+    let open_file = match handles.get(rh.get_handle()) {
+        Ok(f) => f,
+        Err(status) => return ReadFileReply::from_status(status),
+    };
+    match read_at(&mut open_file.file, rh.get_position(), rh.get_length()) {
+        Ok((data, eof)) => {
+            info!(
+                "Read {} bytes at {} in {}",
+                data.len(),
+                rh.get_position(),
+                open_file.path.display()
+            );
+            ReadFileReply::new(Status::Ok, data, eof)
+        }
+        Err(e) => {
+            warn!("Could not read {}. Reason: {}", open_file.path.display(), e);
+            ReadFileReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_write_handle(handles: &mut HandleTable, wh: &WriteHandle) -> WriteFileReply {
+    let open_file = match handles.get(wh.get_handle()) {
+        Ok(f) => f,
+        Err(status) => return WriteFileReply::from_status(status),
+    };
+    match write_at(&mut open_file.file, wh.get_position(), wh.get_content()) {
+        Ok(()) => {
+            info!(
+                "Wrote {} bytes at {} in {}",
+                wh.get_content().len(),
+                wh.get_position(),
+                open_file.path.display()
+            );
+            WriteFileReply::new(Status::Ok, wh.get_content().len() as u64)
+        }
+        Err(e) => {
+            warn!("Could not write in {}. Reason: {}", open_file.path.display(), e);
+            WriteFileReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_stat_handle(handles: &mut HandleTable, sh: &StatHandle) -> StatReply {
+    let open_file = match handles.get(sh.get_handle()) {
+        Ok(f) => f,
+        Err(status) => return StatReply::from_status(status),
+    };
+    match open_file.file.metadata() {
+        Ok(metadata) => StatReply::new(FileStat::from(&metadata)),
+        Err(e) => {
+            warn!("Could not stat {}. Reason: {}", open_file.path.display(), e);
+            StatReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+fn execute_truncate_handle(handles: &mut HandleTable, th: &TruncateHandle) -> TruncateReply {
+    let open_file = match handles.get(th.get_handle()) {
+        Ok(f) => f,
+        Err(status) => return TruncateReply::from_status(status),
+    };
+    match open_file.file.set_len(th.get_length()) {
+        Ok(()) => {
+            info!("Truncate {}", open_file.path.display());
+            TruncateReply::from_status(Status::Ok)
+        }
+        Err(e) => {
+            warn!("Could not truncate {}. Reason: {}", open_file.path.display(), e);
+            TruncateReply::from_status(Status::from(&e))
+        }
+    }
+}
+
+pub(crate) fn remove_newline(s: String) -> String {
+    let mut my_s = s.clone();
+    my_s.pop();
+    my_s
+}
+
+pub(crate) fn client_expected_challenge_response<I>(
+    i: &I,
+    challenge: Challenge,
+) -> (Blowfish, Challenge)
 where
     I: Identity,
 {
     let bf = get_cipher(i.get_secret());
-    let in_buf = GenericArray::from_slice(&challenge);
-    let mut out_buf = GenericArray::new();
-    bf.encrypt_block(&in_buf, &mut out_buf);
-    (bf, out_buf.to_vec())
+    let response = encrypt_block(&bf, &challenge);
+    (bf, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfs_client::{Client as _, RfsClientSession};
+    use std::io::Cursor;
+    use std::thread;
+    use tempfile::TempDir;
+
+    /// Returns the configuration of the server `srv1` exporting `root` on `port`, over TLS if
+    /// `tls` names its certificates and private key. Client `cli1` can read and write, `cli2` can
+    /// only read.
+    fn config(root: &Path, port: u16, tls: Option<(&Path, &Path)>) -> RfsConfig {
+        let tls = tls.map_or(String::from(":"), |(certificates, private_key)| {
+            format!("{}:{}", certificates.display(), private_key.display())
+        });
+        let content = format!(
+            "server:srv1:enl4d3Z1:127.0.0.1:{}:{}:{}\n\
+             client:cli1:MTIzNDU2\n\
+             client:cli2:YWJjZGVm::ro\n",
+            port,
+            tls,
+            root.display()
+        );
+        let (conf, errors) = RfsConfig::parse_content(Path::new("rfs_config"), &content);
+        assert!(errors.is_empty());
+        conf
+    }
+
+    /// Start a server exporting a new temporary directory, serving at most `max_clients` at
+    /// once, and returns the directory and the configuration clients use to reach the server.
+    fn start_server(tls: Option<(&Path, &Path)>, max_clients: usize) -> (TempDir, RfsConfig) {
+        let root = tempfile::tempdir().unwrap();
+        let name = String::from("srv1");
+        let socket = String::from("127.0.0.1:0");
+        let mut server = RfsServer::bound_to(name, config(root.path(), 1, tls), socket).unwrap();
+        server.set_max_clients(max_clients);
+        let port = server.listener.local_addr().unwrap().port();
+        thread::spawn(move || server.listen());
+        let config = config(root.path(), port, tls);
+        (root, config)
+    }
+
+    fn connect(client: &str, config: &RfsConfig) -> RfsClientSession {
+        let mut session =
+            RfsClientSession::new(String::from("srv1"), String::from(client), config.clone())
+                .unwrap();
+        session.connect().unwrap();
+        session
+    }
+
+    /// Upload, list, download, rename and remove files through a session with the server.
+    fn round_trip(config: &RfsConfig, root: &Path) {
+        let mut session = connect("cli1", config);
+        let content: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        session.set_chunk_length(4096);
+        session.mkdir("dir").unwrap();
+        let uploaded = session.upload(Cursor::new(content.clone()), "dir/file").unwrap();
+        assert_eq!(uploaded, content.len() as u64);
+        assert_eq!(fs::read(root.join("dir/file")).unwrap(), content);
+        assert_eq!(session.stat("dir/file").unwrap().get_size(), content.len() as u64);
+        let entries = session.list_dir("dir").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].get_name(), b"file");
+
+        let mut downloaded = Vec::new();
+        session.download("dir/file", &mut downloaded).unwrap();
+        assert_eq!(downloaded, content);
+
+        session.rename("dir/file", "moved").unwrap();
+        assert_eq!(session.stat("dir/file").unwrap_err().get_status(), Some(Status::NotFound));
+        session.unlink("moved").unwrap();
+        session.rmdir("dir").unwrap();
+        assert!(fs::read_dir(root).unwrap().next().is_none());
+        session.disconnect().unwrap();
+    }
+
+    #[test]
+    fn serves_clients_over_loopback() {
+        let (root, config) = start_server(None, DEFAULT_MAX_CLIENTS);
+        round_trip(&config, root.path());
+    }
+
+    #[test]
+    fn caps_the_chunk_length() {
+        let (_root, config) = start_server(None, DEFAULT_MAX_CLIENTS);
+        let mut session = connect("cli1", &config);
+        session.set_chunk_length(0);
+        assert_eq!(session.get_chunk_length(), 1);
+        session.set_chunk_length(MAX_CHUNK_LENGTH + 1);
+        assert_eq!(session.get_chunk_length(), MAX_CHUNK_LENGTH);
+        session.disconnect().unwrap();
+    }
+
+    #[test]
+    fn enforces_permissions_of_clients() {
+        let (root, config) = start_server(None, DEFAULT_MAX_CLIENTS);
+        fs::write(root.path().join("file"), b"content").unwrap();
+        let mut session = connect("cli2", &config);
+        let mut downloaded = Vec::new();
+        session.download("file", &mut downloaded).unwrap();
+        assert_eq!(downloaded, b"content");
+        let denied = Some(Status::PermissionDenied);
+        let error = session.unlink("file").unwrap_err();
+        assert_eq!(error.get_status(), denied);
+        assert_eq!(error.to_string(), "RfsClient Error: the server replied PermissionDenied");
+        assert_eq!(session.upload(Cursor::new(b"new"), "new").unwrap_err().get_status(), denied);
+        assert_eq!(session.stat("../").unwrap_err().get_status(), denied);
+        session.disconnect().unwrap();
+
+        let mut session = connect("cli1", &config);
+        assert_eq!(session.rmdir("").unwrap_err().get_status(), denied);
+        assert_eq!(session.rename(".", "moved").unwrap_err().get_status(), denied);
+        assert!(session.list_dir("").is_ok());
+        session.disconnect().unwrap();
+    }
+
+    #[test]
+    fn requires_the_truncate_permission_to_truncate_on_open() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("file"), b"content").unwrap();
+        let sandbox = Sandbox::new(root.path()).unwrap();
+        let mut handles = HandleTable::new();
+        let operations = Some(vec![MessageType::ReadFile, MessageType::WriteFile]);
+        let permissions = Permissions::new(Access::ReadWrite, Vec::new(), operations);
+        let truncate = Open::new("file", false, true, false, true, false, false);
+        let reply = execute_open(&sandbox, &permissions, &mut handles, &truncate);
+        assert_eq!(reply.get_status(), Status::PermissionDenied);
+        assert_eq!(fs::read(root.path().join("file")).unwrap(), b"content");
+        let write = Open::new("file", false, true, false, false, false, false);
+        let reply = execute_open(&sandbox, &permissions, &mut handles, &write);
+        assert_eq!(reply.get_status(), Status::Ok);
+
+        let permissions = Permissions::default();
+        let reply = execute_open(&sandbox, &permissions, &mut handles, &truncate);
+        assert_eq!(reply.get_status(), Status::Ok);
+        assert_eq!(fs::read(root.path().join("file")).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_unknown_clients() {
+        let (_root, mut config) = start_server(None, DEFAULT_MAX_CLIENTS);
+        let impostor = Field::Client {
+            name: String::from("cli3"),
+            key: b"123456".to_vec(),
+            root: None,
+            permissions: Permissions::default(),
+        };
+        config.add_field(impostor).unwrap();
+        let mut session =
+            RfsClientSession::new(String::from("srv1"), String::from("cli3"), config).unwrap();
+        assert!(session.connect().is_err());
+    }
+
+    #[test]
+    fn rejects_clients_without_encryption() {
+        let (_root, mut config) = start_server(None, DEFAULT_MAX_CLIENTS);
+        config.set_encryption(false);
+        let mut session =
+            RfsClientSession::new(String::from("srv1"), String::from("cli1"), config).unwrap();
+        let error = session.connect().unwrap_err();
+        assert!(error.to_string().contains("encryption required"), "{}", error);
+    }
+
+    #[test]
+    fn closes_connections_beyond_the_limit() {
+        let (_root, config) = start_server(None, 1);
+        let session = connect("cli1", &config);
+        let address = config.get_server_address(String::from("srv1")).unwrap();
+        let mut refused = TcpStream::connect(address).unwrap();
+        refused.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = Vec::new();
+        assert_eq!(refused.read_to_end(&mut received).unwrap(), 0);
+        drop(session);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn serves_clients_over_tls() {
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("127.0.0.1")])
+            .unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let certificates = files.path().join("srv1.crt");
+        let private_key = files.path().join("srv1.key");
+        fs::write(&certificates, certified.cert.pem()).unwrap();
+        fs::write(&private_key, certified.key_pair.serialize_pem()).unwrap();
+        let (root, config) = start_server(Some((&certificates, &private_key)), DEFAULT_MAX_CLIENTS);
+        round_trip(&config, root.path());
+    }
 }
@@ -0,0 +1,228 @@
+//! This module confines the paths requested by clients to the directory exported by the server.
+
+use crate::message::Status;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::Result as IoResult;
+use std::path::{Component, Path, PathBuf};
+use std::str;
+
+/// A directory exported to a client. Paths requested by the client are relative to it, and can
+/// not escape it, neither with `..` nor through symbolic links. Access can be further limited to
+/// some subdirectories of the exported directory.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    root: PathBuf,
+    prefixes: Option<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+    /// Create a sandbox exporting `root`, which must be an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> IoResult<Self> {
+        let root = fs::canonicalize(root)?;
+        if root.is_dir() {
+            Ok(Sandbox {
+                root: root,
+                prefixes: None,
+            })
+        } else {
+            Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ))
+        }
+    }
+
+    /// Returns the exported directory.
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Create the sandbox of `subdirectory`, a path relative to the exported directory.
+    pub fn restrict(&self, subdirectory: &str) -> Result<Sandbox, Status> {
+        let root = self.resolve(subdirectory.as_bytes())?;
+        Sandbox::new(root).map_err(|e| Status::from(&e))
+    }
+
+    /// Limit the access to the paths under `prefixes`, relative to the exported directory. An
+    /// empty list of prefixes gives access to the whole exported directory. Prefixes leading out
+    /// of the exported directory are ignored.
+    pub fn limit_to(&self, prefixes: &[String]) -> Sandbox {
+        if prefixes.is_empty() {
+            return self.clone();
+        }
+        let mut allowed = Vec::new();
+        for prefix in prefixes {
+            match normalize(prefix) {
+                Some(p) => allowed.push(self.root.join(p)),
+                None => warn!("Prefix {} escapes the exported directory", prefix),
+            }
+        }
+        Sandbox {
+            root: self.root.clone(),
+            prefixes: Some(allowed),
+        }
+    }
+
+    /// Resolve `path`, as sent by a client, into a path in the exported directory. Absolute
+    /// paths, `..` components and symbolic links leading out of the exported directory are
+    /// rejected with `Status::PermissionDenied`.
+    ///
+    /// All the directories leading to the path must exist, but the last component may not (e.g.
+    /// for a file about to be created). If the last component is a symbolic link, it is not
+    /// resolved, but its target must be in the exported directory.
+    pub fn resolve(&self, path: &[u8]) -> Result<PathBuf, Status> {
+        let path = str::from_utf8(path).map_err(|e| {
+            warn!("Invalid path. Reason: {}", e);
+            Status::BadMessage
+        })?;
+        let mut relative = match normalize(path) {
+            Some(relative) => relative,
+            None => {
+                warn!("Path {} escapes the exported directory", path);
+                return Err(Status::PermissionDenied);
+            }
+        };
+        let name = match relative.file_name() {
+            Some(name) => name.to_os_string(),
+            None => {
+                self.check_prefixes(path, &self.root)?;
+                return Ok(self.root.clone());
+            }
+        };
+        relative.pop();
+        let parent = fs::canonicalize(self.root.join(relative)).map_err(
+            |e| Status::from(&e),
+        )?;
+        self.check(path, &parent)?;
+        let resolved = parent.join(name);
+        self.check_prefixes(path, &resolved)?;
+        if let Ok(metadata) = fs::symlink_metadata(&resolved) {
+            if metadata.file_type().is_symlink() {
+                let target = fs::canonicalize(&resolved).map_err(|e| {
+                    warn!("Can not resolve link {}. Reason: {}", path, e);
+                    Status::PermissionDenied
+                })?;
+                self.check(path, &target)?;
+                self.check_prefixes(path, &target)?;
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn check_prefixes(&self, requested: &str, path: &Path) -> Result<(), Status> {
+        let allowed = match self.prefixes {
+            Some(ref prefixes) => prefixes.iter().any(|p| path.starts_with(p)),
+            None => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            warn!("Path {} is not in the prefixes allowed to the client", requested);
+            Err(Status::PermissionDenied)
+        }
+    }
+
+    fn check(&self, requested: &str, path: &Path) -> Result<(), Status> {
+        if path.starts_with(&self.root) {
+            Ok(())
+        } else {
+            warn!("Path {} escapes the exported directory", requested);
+            Err(Status::PermissionDenied)
+        }
+    }
+}
+
+/// Returns `path` without its `.` components, or `None` if it is absolute or contains `..`.
+fn normalize(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(c) => relative.push(c),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// Returns a temporary directory holding `root/dir/file` and `outside/secret`, and the sandbox
+    /// exporting `root`.
+    fn tree() -> (TempDir, Sandbox) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("root/dir")).unwrap();
+        fs::create_dir(dir.path().join("outside")).unwrap();
+        fs::write(dir.path().join("root/dir/file"), b"inside").unwrap();
+        fs::write(dir.path().join("outside/secret"), b"outside").unwrap();
+        let sandbox = Sandbox::new(dir.path().join("root")).unwrap();
+        (dir, sandbox)
+    }
+
+    #[test]
+    fn resolves_paths_under_the_root() {
+        let (_dir, sandbox) = tree();
+        let root = sandbox.get_root().to_path_buf();
+        assert_eq!(sandbox.resolve(b"dir/file"), Ok(root.join("dir/file")));
+        assert_eq!(sandbox.resolve(b"./dir/./new"), Ok(root.join("dir/new")));
+        assert_eq!(sandbox.resolve(b""), Ok(root.clone()));
+        assert_eq!(sandbox.resolve(b"."), Ok(root));
+    }
+
+    #[test]
+    fn rejects_parent_and_absolute_paths() {
+        let (dir, sandbox) = tree();
+        let absolute = dir.path().join("outside/secret");
+        for path in &["..", "../outside/secret", "dir/../../outside", "dir/..", "/etc/passwd"] {
+            assert_eq!(sandbox.resolve(path.as_bytes()), Err(Status::PermissionDenied), "{}", path);
+        }
+        let absolute = absolute.to_str().unwrap().as_bytes();
+        assert_eq!(sandbox.resolve(absolute), Err(Status::PermissionDenied));
+    }
+
+    #[test]
+    fn rejects_symbolic_links_leading_out() {
+        let (dir, sandbox) = tree();
+        let root = sandbox.get_root().to_path_buf();
+        symlink(dir.path().join("outside"), root.join("out")).unwrap();
+        symlink(dir.path().join("outside/secret"), root.join("secret")).unwrap();
+        symlink(root.join("dir/file"), root.join("file")).unwrap();
+        assert_eq!(sandbox.resolve(b"out"), Err(Status::PermissionDenied));
+        assert_eq!(sandbox.resolve(b"out/secret"), Err(Status::PermissionDenied));
+        assert_eq!(sandbox.resolve(b"secret"), Err(Status::PermissionDenied));
+        assert_eq!(sandbox.resolve(b"file"), Ok(root.join("file")));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_and_missing_parents() {
+        let (_dir, sandbox) = tree();
+        assert_eq!(sandbox.resolve(b"dir/\xff"), Err(Status::BadMessage));
+        assert_eq!(sandbox.resolve(b"missing/file"), Err(Status::NotFound));
+    }
+
+    #[test]
+    fn limits_access_to_prefixes() {
+        let (_dir, sandbox) = tree();
+        let root = sandbox.get_root().to_path_buf();
+        let limited = sandbox.limit_to(&[String::from("dir"), String::from("../outside")]);
+        assert_eq!(limited.resolve(b"dir/file"), Ok(root.join("dir/file")));
+        assert_eq!(limited.resolve(b"other"), Err(Status::PermissionDenied));
+        assert_eq!(limited.resolve(b""), Err(Status::PermissionDenied));
+        assert_eq!(sandbox.limit_to(&[]).resolve(b"other"), Ok(root.join("other")));
+    }
+
+    #[test]
+    fn restricts_to_subdirectories() {
+        let (_dir, sandbox) = tree();
+        let restricted = sandbox.restrict("dir").unwrap();
+        assert_eq!(restricted.get_root(), sandbox.get_root().join("dir"));
+        assert_eq!(restricted.resolve(b"../dir/file"), Err(Status::PermissionDenied));
+        assert_eq!(sandbox.restrict("../outside").err(), Some(Status::PermissionDenied));
+        assert_eq!(sandbox.restrict("dir/file").err(), Some(Status::InvalidArgument));
+    }
+}
@@ -0,0 +1,170 @@
+//! TLS transport, used when the config entry of a server names a certificate chain and a private
+//! key. The challenge of `rfs_server::RfsServer` then runs inside the TLS channel.
+//!
+//! Clients trust the certificates found in the file named by the config entry of the server, so
+//! that self-signed certificates can be used without a certificate authority. Such certificates
+//! must name the address of the server, and must not be CA certificates (`CA:FALSE`).
+
+use crate::config::TlsFiles;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection,
+             StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Error as IoError};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// A socket accepted by a server, wrapped in TLS.
+pub type TlsServerStream = StreamOwned<ServerConnection, TcpStream>;
+/// The socket of a client, wrapped in TLS.
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let file = File::open(path).map_err(TlsError::from_io)?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TlsError::from_io)?;
+    if certificates.is_empty() {
+        Err(TlsError::new(TlsErrorKind::NoCertificate(path.to_string())))
+    } else {
+        Ok(certificates)
+    }
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let file = File::open(path).map_err(TlsError::from_io)?;
+    match rustls_pemfile::private_key(&mut BufReader::new(file)).map_err(TlsError::from_io)? {
+        Some(key) => Ok(key),
+        None => Err(TlsError::new(TlsErrorKind::NoPrivateKey(path.to_string()))),
+    }
+}
+
+/// Build the TLS configuration of a server from its certificate chain and private key.
+pub fn server_config(files: &TlsFiles) -> Result<Arc<ServerConfig>, TlsError> {
+    let certificates = load_certificates(files.get_certificates())?;
+    let key = load_private_key(files.get_private_key())?;
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map(Arc::new)
+        .map_err(TlsError::from_rustls)
+}
+
+/// Build the TLS configuration of a client, which trusts the certificates of the server.
+pub fn client_config(files: &TlsFiles) -> Result<Arc<ClientConfig>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(files.get_certificates())? {
+        roots.add(certificate).map_err(TlsError::from_rustls)?;
+    }
+    Ok(Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    ))
+}
+
+/// Wrap a socket accepted by a server. The TLS handshake is performed on the first read or write.
+pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> Result<TlsServerStream, TlsError> {
+    let connection = ServerConnection::new(config.clone()).map_err(TlsError::from_rustls)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Wrap the socket of a client connected to the server `server_name`, i.e. the address of the
+/// server, which must match its certificate. The TLS handshake is performed on the first read or
+/// write.
+pub fn connect(
+    config: &Arc<ClientConfig>,
+    server_name: &str,
+    stream: TcpStream,
+) -> Result<TlsClientStream, TlsError> {
+    let name = ServerName::try_from(server_name.to_string()).map_err(|_| {
+        TlsError::new(TlsErrorKind::InvalidServerName(server_name.to_string()))
+    })?;
+    let connection = ClientConnection::new(config.clone(), name).map_err(
+        TlsError::from_rustls,
+    )?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+#[derive(Debug)]
+enum TlsErrorKind {
+    Io(IoError),
+    NoCertificate(String),
+    NoPrivateKey(String),
+    InvalidServerName(String),
+    Rustls(rustls::Error),
+}
+
+/// The error type for the setup of TLS.
+#[derive(Debug)]
+pub struct TlsError {
+    kind: TlsErrorKind,
+}
+
+impl TlsError {
+    fn new(kind: TlsErrorKind) -> Self {
+        TlsError { kind: kind }
+    }
+
+    fn from_io(e: IoError) -> Self {
+        TlsError::new(TlsErrorKind::Io(e))
+    }
+
+    fn from_rustls(e: rustls::Error) -> Self {
+        TlsError::new(TlsErrorKind::Rustls(e))
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            TlsErrorKind::Io(ref e) => write!(f, "TLS Error: {}", e),
+            TlsErrorKind::NoCertificate(ref path) => {
+                write!(f, "TLS Error: {} does not contain any certificate", path)
+            }
+            TlsErrorKind::NoPrivateKey(ref path) => {
+                write!(f, "TLS Error: {} does not contain any private key", path)
+            }
+            TlsErrorKind::InvalidServerName(ref name) => {
+                write!(f, "TLS Error: `{}` is not a valid server name", name)
+            }
+            TlsErrorKind::Rustls(ref e) => write!(f, "TLS Error: {}", e),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn description(&self) -> &str {
+        match self.kind {
+            TlsErrorKind::Io(_) => "Could not read a certificate or key file",
+            TlsErrorKind::NoCertificate(_) => "The file does not contain any certificate",
+            TlsErrorKind::NoPrivateKey(_) => "The file does not contain any private key",
+            TlsErrorKind::InvalidServerName(_) => "The address of the server is not a valid name",
+            TlsErrorKind::Rustls(_) => "The TLS configuration is invalid",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn names_files_without_certificates_or_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.pem");
+        fs::write(&path, b"").unwrap();
+        let path = path.to_str().unwrap();
+        let files = TlsFiles::new(path.to_string(), path.to_string());
+        let error = client_config(&files).unwrap_err();
+        let expected = format!("TLS Error: {} does not contain any certificate", path);
+        assert_eq!(error.to_string(), expected);
+        let error = load_private_key(path).unwrap_err();
+        let expected = format!("TLS Error: {} does not contain any private key", path);
+        assert_eq!(error.to_string(), expected);
+    }
+}
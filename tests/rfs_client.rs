@@ -0,0 +1,134 @@
+//! Runs the `rfs_client` tool against a server listening on the loopback interface.
+
+#![allow(clippy::redundant_field_names)]
+
+use rfs::config::RfsConfig;
+use rfs::rfs_server::{RfsServer, Server};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread;
+use tempfile::TempDir;
+
+/// A server exporting a temporary directory, and the configuration file of its client `cli1`.
+struct Fixture {
+    root: TempDir,
+    local: TempDir,
+    config: PathBuf,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let root = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let entries = |port: u16| {
+            format!(
+                "server:srv1:enl4d3Z1:127.0.0.1:{}:::{}\nclient:cli1:MTIzNDU2\n",
+                port,
+                root.path().display()
+            )
+        };
+        let config = local.path().join("rfs_config");
+        fs::write(&config, entries(1)).unwrap();
+        let server_config = RfsConfig::load(&config).unwrap();
+        let server =
+            RfsServer::bound_to(String::from("srv1"), server_config, String::from("127.0.0.1:0"))
+                .unwrap();
+        let port = server.get_local_address().unwrap().port();
+        thread::spawn(move || server.listen());
+        fs::write(&config, entries(port)).unwrap();
+        Fixture {
+            root: root,
+            local: local,
+            config: config,
+        }
+    }
+
+    /// Run `rfs_client` with `args`, quietly.
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_rfs_client"))
+            .arg("-q")
+            .arg("-c")
+            .arg(&self.config)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn local(&self, name: &str) -> String {
+        self.local.path().join(name).to_str().unwrap().to_string()
+    }
+
+    fn remote(&self, name: &str) -> PathBuf {
+        self.root.path().join(name)
+    }
+}
+
+fn status(output: &Output) -> Option<i32> {
+    output.status.code()
+}
+
+#[test]
+fn puts_and_gets_files() {
+    let fixture = Fixture::new();
+    let content: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    fs::write(fixture.local("file"), &content).unwrap();
+    let output = fixture.run(&["--chunk-size", "1000", "put", &fixture.local("file"), "file"]);
+    assert_eq!(status(&output), Some(0), "{:?}", output);
+    assert_eq!(fs::read(fixture.remote("file")).unwrap(), content);
+
+    let output = fixture.run(&["get", "file", &fixture.local("copy")]);
+    assert_eq!(status(&output), Some(0), "{:?}", output);
+    assert_eq!(fs::read(fixture.local("copy")).unwrap(), content);
+    assert!(!Path::new(&fixture.local("copy.part")).exists());
+}
+
+#[test]
+fn keeps_local_files_when_get_fails() {
+    let fixture = Fixture::new();
+    fs::write(fixture.local("copy"), b"previous").unwrap();
+    let output = fixture.run(&["get", "missing", &fixture.local("copy")]);
+    assert_eq!(status(&output), Some(1));
+    assert_eq!(fs::read(fixture.local("copy")).unwrap(), b"previous");
+    assert!(!Path::new(&fixture.local("copy.part")).exists());
+
+    fs::write(fixture.remote("file"), b"content").unwrap();
+    let output = fixture.run(&["get", "file", &fixture.local("missing/copy")]);
+    assert_eq!(status(&output), Some(1));
+}
+
+#[test]
+fn lists_removes_and_moves_files() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.remote("dir")).unwrap();
+    fs::write(fixture.remote("file"), b"content").unwrap();
+    let output = fixture.run(&["ls"]);
+    assert_eq!(status(&output), Some(0), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "dir/\nfile\n");
+
+    let output = fixture.run(&["mv", "file", "dir/moved"]);
+    assert_eq!(status(&output), Some(0), "{:?}", output);
+    assert_eq!(fs::read(fixture.remote("dir/moved")).unwrap(), b"content");
+    let output = fixture.run(&["rm", "dir/moved"]);
+    assert_eq!(status(&output), Some(0), "{:?}", output);
+    assert!(!fixture.remote("dir/moved").exists());
+
+    let output = fixture.run(&["rm", "dir/moved"]);
+    assert_eq!(status(&output), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("rm: "));
+    let output = fixture.run(&["mv", "missing", "moved"]);
+    assert_eq!(status(&output), Some(1));
+}
+
+#[test]
+fn rejects_invalid_command_lines() {
+    let fixture = Fixture::new();
+    for args in &[&[][..], &["cp", "a", "b"], &["rm"], &["--chunk-size", "0", "ls"], &["-x"]] {
+        assert_eq!(status(&fixture.run(args)), Some(2), "{:?}", args);
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_rfs_client"))
+        .args(["-c", &fixture.local("missing"), "ls"])
+        .output()
+        .unwrap();
+    assert_eq!(status(&output), Some(1));
+}